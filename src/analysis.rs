@@ -0,0 +1,255 @@
+use crate::course::{Course, Overlay, SectionKind};
+use crate::number_format::NumberFormat;
+
+/// Score needed on a section to reach a letter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Needed {
+    /// Reached even with 0% on the section
+    Secured,
+    /// Percentage needed on the section
+    Score(f32),
+    /// Out of reach even with 100%
+    Unreachable,
+}
+
+impl Needed {
+    pub fn describe(&self, format: NumberFormat) -> String {
+        match self {
+            Needed::Secured => "secured".to_string(),
+            Needed::Score(score) => format.percent(*score, 1),
+            Needed::Unreachable => "out of reach".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LetterTarget {
+    pub letter: String,
+    pub min: f32,
+    pub needed: Needed,
+}
+
+/// How much an unfinished section matters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Focus {
+    pub row_id: u64,
+    pub section: String,
+    /// Final-grade points gained per percentage point on this section
+    pub impact: f32,
+    pub targets: Vec<LetterTarget>,
+}
+
+impl Focus {
+    /// The section's impact and the score it needs for every letter, one letter per line.
+    pub fn describe(&self, format: NumberFormat) -> String {
+        let mut text = format!("{}: {} points per percentage point", self.section, format.number(self.impact, 3));
+        for target in self.targets.iter() {
+            text += &format!("\n    {} ({}): {}", target.letter, format.percent(target.min, 1), target.needed.describe(format));
+        }
+        text
+    }
+}
+
+/// One section's share of the final grade, in final-grade points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub row_id: u64,
+    pub section: String,
+    pub earned: f32,
+    pub lost: f32,
+    pub ungraded: f32,
+}
+
+/// Points each section earned, lost or still has open, from the row weights of the scheme in use
+/// and the raw section grades. Extra credit only ever adds points.
+pub fn breakdown(course: &Course) -> Vec<Contribution> {
+    let scheme = course.evaluate().map(|e| e.scheme).unwrap_or(0);
+    course
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let weight = row.weight(scheme).trim().parse::<f32>().ok().filter(|w| *w > 0.0)?;
+            let extra = row.kind == SectionKind::ExtraCredit;
+            let (earned, lost, ungraded) = match row.f_section_grade {
+                Some(grade) => {
+                    let earned = grade.max(0.0) * weight;
+                    (earned, if extra { 0.0 } else { (weight - earned).max(0.0) }, 0.0)
+                }
+                None => (0.0, 0.0, if extra { 0.0 } else { weight }),
+            };
+            Some(Contribution { row_id: row.id, section: row.section.clone(), earned, lost, ungraded })
+        })
+        .collect()
+}
+
+/// Current average of the graded sections as a fraction, used as the projection
+/// for every unfinished section that isn't being varied.
+pub fn current_average(course: &Course) -> Option<f32> {
+    let eval = course.evaluate().ok()?;
+    let sections = course.sections(eval.scheme, &Overlay::new()).ok()?;
+    let graded_weight: f32 = sections.iter().filter(|s| s.grade.is_some()).map(|s| s.weight).sum();
+    if graded_weight > 0.0 { Some(eval.weighted / graded_weight) } else { None }
+}
+
+/// Row ids of the sections that carry weight but have no marks yet.
+pub fn unfinished(course: &Course) -> Vec<u64> {
+    course
+        .rows
+        .iter()
+        .filter(|r| r.f_section_grade.is_none() && r.marks.trim().is_empty())
+        .filter(|r| r.weights.iter().any(|w| w.trim().parse::<f32>().is_ok_and(|w| w > 0.0)))
+        .map(|r| r.id)
+        .collect()
+}
+
+/// Projected final grade when `row_id` scores `grade` (a fraction) and every other
+/// unfinished section scores `average`, the course's `current_average`.
+pub fn projected_grade(course: &Course, average: f32, row_id: u64, grade: f32) -> Option<f32> {
+    let mut overlay: Overlay = unfinished(course).into_iter().map(|id| (id, average)).collect();
+    overlay.insert(row_id, grade);
+    course.evaluate_with(&overlay).ok().map(|e| e.grade)
+}
+
+/// (score %, final grade %) pairs for `samples` evenly spaced scores from 0 to 100 on the section,
+/// with the other unfinished sections at `average`.
+pub fn grade_curve(course: &Course, average: f32, row_id: u64, samples: usize) -> Vec<(f32, f32)> {
+    let samples = samples.max(2);
+    (0..samples)
+        .filter_map(|i| {
+            let score = i as f32 / (samples - 1) as f32;
+            projected_grade(course, average, row_id, score).map(|grade| (score * 100.0, grade))
+        })
+        .collect()
+}
+
+// Lowest score on the section that reaches `min`, assuming the grade never drops as the score rises
+fn needed_for(f: &impl Fn(f32) -> Option<f32>, min: f32) -> Needed {
+    let reaches = |g: f32| f(g).is_some_and(|grade| grade >= min);
+    if reaches(0.0) {
+        return Needed::Secured;
+    }
+    if !reaches(1.0) {
+        return Needed::Unreachable;
+    }
+    let (mut lo, mut hi) = (0.0_f32, 1.0_f32);
+    for _ in 0..40 {
+        let mid = (lo + hi) / 2.0;
+        if reaches(mid) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Needed::Score(hi * 100.0)
+}
+
+/// Unfinished sections ranked by how many final-grade points each percentage point is worth,
+/// with the score each one needs for every letter on the course's scale.
+pub fn focus_list(course: &Course) -> Vec<Focus> {
+    let average = current_average(course).unwrap_or(0.0);
+    let mut list: Vec<Focus> = unfinished(course)
+        .into_iter()
+        .filter_map(|id| {
+            let row = course.row(id)?;
+            let f = |g: f32| projected_grade(course, average, id, g);
+            let impact = (f(1.0)? - f(0.0)?) / 100.0;
+            let targets = course
+                .scale
+                .sorted()
+                .into_iter()
+                .map(|step| LetterTarget {
+                    letter: step.letter.clone(),
+                    min: step.min,
+                    needed: needed_for(&f, step.min),
+                })
+                .collect();
+            Some(Focus { row_id: id, section: row.section.clone(), impact, targets })
+        })
+        .collect();
+    list.sort_by(|a, b| b.impact.total_cmp(&a.impact));
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{close, course};
+
+    #[test]
+    fn test_focus_ranking() {
+        let c = course(
+            &[("Assignments", "40/50", "30"), ("Project", "", "20"), ("Final", "", "50")],
+            "",
+        );
+        let list = focus_list(&c);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].section, "Final");
+        assert!(close(list[0].impact, 0.5));
+        assert!(close(list[1].impact, 0.2));
+    }
+
+    #[test]
+    fn test_needed_scores() {
+        let c = course(&[("Assignments", "40/50", "50"), ("Final", "", "50")], "");
+        let list = focus_list(&c);
+        let needed = |letter: &str| list[0].targets.iter().find(|t| t.letter == letter).unwrap().needed;
+
+        // 40 points secured, an A (93) needs 106% and a C (73) needs 66%
+        assert_eq!(needed("A"), Needed::Unreachable);
+        assert_eq!(needed("F"), Needed::Secured);
+        match needed("C") {
+            Needed::Score(score) => assert!((score - 66.0).abs() < 1e-2),
+            other => panic!("expected a score, got {:?}", other),
+        }
+        let comma = NumberFormat { decimal: crate::number_format::DecimalSeparator::Comma };
+        assert_eq!(needed("C").describe(comma), "66,0%");
+        assert!(list[0].describe(comma).starts_with("Final: 0,500 points per percentage point\n    A+ (97,0%): out of reach"));
+    }
+
+    #[test]
+    fn test_grade_curve() {
+        let c = course(
+            &[("Assignments", "45/50", "50"), ("Project", "", "20"), ("Final", "", "30")],
+            "require Final >= 50 else 40",
+        );
+        let final_id = c.rows[2].id;
+        let average = current_average(&c).unwrap();
+        assert!(close(average, 0.9));
+        let curve = grade_curve(&c, average, final_id, 11);
+        assert_eq!(curve.len(), 11);
+        assert_eq!(curve[0], (0.0, 40.0));
+        // 45 + 18 from the project at the average, plus 30% of the score
+        assert!(close(curve[10].1, 93.0));
+        assert!(close(curve[5].1, 78.0));
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let mut c = course(
+            &[("Assignments", "40/50", "30"), ("Final", "", "70"), ("Bonus", "1/2", "4")],
+            "",
+        );
+        c.rows[2].kind = SectionKind::ExtraCredit;
+        let parts = breakdown(&c);
+        assert_eq!(parts.len(), 3);
+        assert!(close(parts[0].earned, 24.0));
+        assert!(close(parts[0].lost, 6.0));
+        assert_eq!((parts[1].earned, parts[1].ungraded), (0.0, 70.0));
+        assert_eq!((parts[2].earned, parts[2].lost), (2.0, 0.0));
+    }
+
+    #[test]
+    fn test_rules_change_the_impact() {
+        let c = course(
+            &[("Assignments", "45/50", "50"), ("Final", "", "50")],
+            "require Final >= 50 else 40",
+        );
+        let list = focus_list(&c);
+        // With 0% the grade is capped at 40, with 100% it is 95
+        assert!(close(list[0].impact, 0.55));
+        match list[0].targets.iter().find(|t| t.letter == "D-").unwrap().needed {
+            Needed::Score(score) => assert!((score - 50.0).abs() < 1e-2),
+            other => panic!("expected a score, got {:?}", other),
+        }
+    }
+}
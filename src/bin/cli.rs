@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::time::Instant;
+
+use grade_calculator::analysis;
+use grade_calculator::calculator::{Calculator, ParseOptions};
+use grade_calculator::course::Course;
+use grade_calculator::number_format::NumberFormat;
+use grade_calculator::program::Program;
+use grade_calculator::solver;
+use grade_calculator::trace;
+
+const USAGE: &str = "Usage: GradeCalculatorCli [--focus <course.json> | --explain <course.json> | --bench [formula]]";
+
+const BENCH_FORMULA: &str = "(25 + 10 + x) / (50 + 20 + 80) * clamp(y, 0, 1.1) + round(z) / 100";
+const BENCH_BINDINGS: usize = 100_000;
+
+// The terminal side of the calculator, kept apart from the GUI binary so its output
+// isn't lost where the GUI has no console
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [flag, path] if flag == "--focus" => print_focus(Path::new(path)),
+        [flag, path] if flag == "--explain" => print_explanation(Path::new(path)),
+        [flag] if flag == "--bench" => print_benchmark(BENCH_FORMULA),
+        [flag, formula] if flag == "--bench" => print_benchmark(formula),
+        _ => eprintln!("{}", USAGE),
+    }
+}
+
+fn print_focus(path: &Path) {
+    let course = match Course::load(path) {
+        Ok(course) => course,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", path.display(), e);
+            return;
+        }
+    };
+    match course.evaluate() {
+        Ok(eval) => println!("Current grade: {}", course.number_format.percent(eval.grade, 2)),
+        Err(e) => println!("Current grade: {}", e),
+    }
+    let list = analysis::focus_list(&course);
+    if list.is_empty() {
+        println!("No unfinished sections");
+    }
+    for (i, focus) in list.iter().enumerate() {
+        println!("{}. {}", i + 1, focus.describe(course.number_format));
+    }
+}
+
+fn print_explanation(path: &Path) {
+    let course = match Course::load(path) {
+        Ok(course) => course,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", path.display(), e);
+            return;
+        }
+    };
+    match trace::explain(&course) {
+        Ok(steps) => print!("{}", trace::to_text(&course, &steps)),
+        Err(e) => println!("Could not calculate the final grade: {}", e),
+    }
+}
+
+/// Times one formula over many sets of values: re-parsing the text each time, walking the tree,
+/// and running it compiled.
+fn print_benchmark(formula: &str) {
+    let (format, options) = (NumberFormat::default(), ParseOptions::default());
+    let compiled = Calculator::parse_in(formula, format, options).and_then(|expr| Program::compile(&expr).map(|p| (expr, p)));
+    let (expr, program) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            eprintln!("Could not compile {}: {}", formula, e);
+            return;
+        }
+    };
+    let names = program.slots().to_vec();
+    // The same spread of scores between 0 and 100 on every run
+    let bindings: Vec<Vec<f32>> = (0..BENCH_BINDINGS)
+        .map(|i| (0..names.len()).map(|j| ((i * 7919 + j * 104_729) % 10_000) as f32 / 100.0).collect())
+        .collect();
+    let unknowns = if names.is_empty() { "no unknowns".to_string() } else { names.join(", ") };
+    println!("{} over {} values of {}", formula, BENCH_BINDINGS, unknowns);
+
+    // Writing the values into the text isn't part of re-parsing, so it happens before the clock starts
+    let texts: Vec<String> = bindings
+        .iter()
+        .map(|values| {
+            names.iter().zip(values).fold(formula.to_string(), |text, (name, value)| {
+                solver::substitute(&text, name, *value, format)
+            })
+        })
+        .collect();
+
+    let time = |label: &str, run: &mut dyn FnMut(usize) -> Result<f32, String>| {
+        let start = Instant::now();
+        let (mut checksum, mut failed) = (0.0_f64, 0);
+        for i in 0..bindings.len() {
+            match run(i) {
+                Ok(value) => checksum += value as f64,
+                Err(_) => failed += 1,
+            }
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        println!(
+            "{:<9} {:>9.1} ms {:>13.0} per second   checksum {:.3}, {} failed",
+            label,
+            seconds * 1000.0,
+            BENCH_BINDINGS as f64 / seconds,
+            checksum,
+            failed
+        );
+    };
+    time("re-parse", &mut |i| Calculator::str_2_f_in(&texts[i], format, options));
+    time("tree", &mut |i| {
+        let values = &bindings[i];
+        expr.evaluate_with(&|name| names.iter().position(|n| n == name).map(|slot| values[slot]))
+    });
+    let mut stack = Vec::new();
+    time("compiled", &mut |i| program.evaluate_in(&bindings[i], &mut stack));
+}
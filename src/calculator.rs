@@ -1,4 +1,32 @@
 use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::number_format::NumberFormat;
+
+/// Byte range of a token or sub-expression in the source text.
+pub type Span = Range<usize>;
+
+/// A problem in the marks text and where it is.
+pub type Diagnostic = (ParseError, Span);
+
+/// Whether two operands side by side, as in `2(3 + 4)`, multiply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Multiplication {
+    /// `2(3)`, `(1)(2)`, `2x` and `2 3` all multiply
+    #[default]
+    Implicit,
+    /// Every multiplication needs a `*`
+    Explicit,
+}
+
+/// Which expressions the parser accepts. Unlike the `NumberFormat`, changing them never rewrites the marks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ParseOptions {
+    #[serde(default)]
+    pub multiplication: Multiplication,
+}
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -7,7 +35,10 @@ pub enum ParseError {
     UnexpectedToken(String),
     UnexpectedEndOfExpression,
     MissingClosingParenthesis,
+    MissingOperator,
     EmptyExpression,
+    UnknownFunction(String),
+    ArgumentCount { name: String, expected: usize, found: usize },
 }
 
 impl fmt::Display for ParseError {
@@ -18,154 +49,192 @@ impl fmt::Display for ParseError {
             ParseError::UnexpectedToken(s) => write!(f, "Unexpected token: {}", s),
             ParseError::UnexpectedEndOfExpression => write!(f, "Unexpected end of expression"),
             ParseError::MissingClosingParenthesis => write!(f, "Missing closing parenthesis"),
+            ParseError::MissingOperator => write!(f, "Missing operator: write '*' to multiply"),
             ParseError::EmptyExpression => write!(f, "Empty expression"),
+            ParseError::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            ParseError::ArgumentCount { name, expected, found } => {
+                write!(f, "{} takes {} argument{}, found {}", name, expected, if *expected == 1 { "" } else { "s" }, found)
+            }
         }
     }
 }
 
 impl std::error::Error for ParseError {}
 
-#[derive(Clone, PartialEq, Debug)]
-pub enum Token {
+// Functions marks can call, with their number of arguments
+const FUNCTIONS: &[(&str, usize)] = &[("round", 1), ("floor", 1), ("ceil", 1), ("sqrt", 1), ("clamp", 3)];
+
+fn arity(name: &str) -> Option<usize> {
+    FUNCTIONS.iter().find(|(f, _)| *f == name).map(|(_, n)| *n)
+}
+
+/// Whether `name` is a function rather than an unknown when followed by `(`.
+pub fn is_function(name: &str) -> bool {
+    arity(name).is_some()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Token<'a> {
     Number(f32),
     Operator(char),
+    /// Name of an unknown, such as `x`, borrowed from the text
+    Ident(&'a str),
+    /// The `..` between the bounds of a range
+    Range,
     EOF,
 }
 
-pub struct Lexer {
-    tokens: Vec<Token>,
-    current_token: usize,
+// Whether the text after an `e` is an exponent: digits, optionally after a sign
+fn exponent_follows(rest: &str) -> bool {
+    let digits = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+    digits.starts_with(|c: char| c.is_ascii_digit())
 }
 
-impl Lexer {
-    pub fn new(expr: &str) -> Result<Lexer, ParseError> {
-        let tokens = Lexer::tokenize(expr)?;
-        Ok(Lexer {
-            tokens,
-            current_token: 0,
-        })
+// Length of the number at the start of `text`: digits with `_`, decimal separators and an exponent,
+// stopping before the `..` of a range
+fn number_len(text: &str, decimal: char) -> usize {
+    let mut seen_exponent = false;
+    let mut end = text.chars().next().map_or(0, char::len_utf8);
+    while let Some(c) = text[end..].chars().next() {
+        let rest = &text[end + c.len_utf8()..];
+        if c.is_ascii_digit() || c == '_' {
+            end += 1;
+        } else if c == decimal && !text[end..].starts_with("..") {
+            // A second separator, as in `1.2.3`, makes the whole number invalid
+            // rather than a second number that would multiply the first
+            end += c.len_utf8();
+        } else if matches!(c, 'e' | 'E') && !seen_exponent && exponent_follows(rest) {
+            // `1e2` or `1.5e-3`, but not the `e` of an unknown after a number
+            seen_exponent = true;
+            end += if rest.starts_with(['+', '-']) { 2 } else { 1 };
+        } else {
+            break;
+        }
     }
+    end
+}
 
-    fn tokenize(expr: &str) -> Result<Vec<Token>, ParseError> {
-        let mut tokens: Vec<Token> = Vec::new();
-        let mut current_number = String::new();
-        let mut chars = expr.chars().peekable();
-
-        while let Some(&c) = chars.peek() {
-            match c {
-                '0'..='9' | '.' => {
-                    current_number.push(c);
-                    chars.next();
-                    
-                    // Continue collecting the number
-                    while let Some(&next_c) = chars.peek() {
-                        if next_c.is_digit(10) || (next_c == '.' && !current_number.contains('.')) {
-                            current_number.push(next_c);
-                            chars.next();
-                        } else {
-                            break;
-                        }
-                    }
-                    
-                    // Parse the collected number
-                    match current_number.parse::<f32>() {
-                        Ok(num) => {
-                            tokens.push(Token::Number(num));
-                            
-                            // Check for implicit multiplication: number followed by '('
-                            // Skip any whitespace first
-                            while let Some(&next_c) = chars.peek() {
-                                if next_c.is_whitespace() {
-                                    chars.next();
-                                } else {
-                                    break;
-                                }
-                            }
-                            
-                            // If next non-whitespace char is '(', insert multiplication
-                            if let Some(&'(') = chars.peek() {
-                                tokens.push(Token::Operator('*'));
-                            }
-                        }
-                        Err(_) => return Err(ParseError::InvalidNumber(current_number)),
-                    }
-                    current_number.clear();
-                }
-                '+' | '-' | '*' | '/' | '(' | ')' => {
-                    // Handle ')' followed by '(' or number for implicit multiplication
-                    if c == ')' {
-                        tokens.push(Token::Operator(c));
-                        chars.next();
-                        
-                        // Skip whitespace
-                        while let Some(&next_c) = chars.peek() {
-                            if next_c.is_whitespace() {
-                                chars.next();
-                            } else {
-                                break;
-                            }
-                        }
-                        
-                        // Check if next is '(' or a number
-                        if let Some(&next_c) = chars.peek() {
-                            if next_c == '(' || next_c.is_digit(10) {
-                                tokens.push(Token::Operator('*'));
-                            }
+/// Reads tokens one at a time straight from the text, without copying it. Bad characters and
+/// numbers are skipped and noted in `errors`.
+pub struct Lexer<'a> {
+    text: &'a str,
+    format: NumberFormat,
+    // Byte offset of the next character to read
+    pos: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Lexer<'a> {
+    /// Reads numbers and argument separators as `format` writes them. The argument separator
+    /// always becomes `Token::Operator(',')`.
+    pub fn new(text: &'a str, format: NumberFormat) -> Self {
+        Lexer { text, format, pos: 0, errors: Vec::new() }
+    }
+
+    /// Tokens with their spans, with numbers written the default way.
+    #[cfg(test)]
+    pub fn tokenize(expr: &'a str) -> Result<Vec<(Token<'a>, Span)>, Diagnostic> {
+        Lexer::tokenize_in(expr, NumberFormat::default())
+    }
+
+    /// Every token with its span, ending with `Token::EOF`, or the first error with the span of the offending text.
+    pub fn tokenize_in(expr: &'a str, format: NumberFormat) -> Result<Vec<(Token<'a>, Span)>, Diagnostic> {
+        let mut lexer = Lexer::new(expr, format);
+        let mut tokens: Vec<(Token, Span)> = lexer.by_ref().collect();
+        if let Some(error) = lexer.errors.into_iter().next() {
+            return Err(error);
+        }
+        if tokens.is_empty() {
+            return Err((ParseError::EmptyExpression, 0..expr.len()));
+        }
+        tokens.push((Token::EOF, expr.len()..expr.len()));
+        Ok(tokens)
+    }
+
+    /// The next token, or `Token::EOF` over and over once the text runs out.
+    pub fn read(&mut self) -> (Token<'a>, Span) {
+        let decimal = self.format.decimal_char();
+        loop {
+            let start = self.pos;
+            let rest = &self.text[start..];
+            let Some(c) = rest.chars().next() else {
+                return (Token::EOF, start..start);
+            };
+            let (token, len) = match c {
+                ' ' | '\t' | '\n' => (None, 1),
+                '.' if rest.starts_with("..") => (Some(Token::Range), 2),
+                c if c.is_ascii_digit() || c == decimal => {
+                    let len = number_len(rest, decimal);
+                    match self.format.parse(&rest[..len]) {
+                        Some(num) => (Some(Token::Number(num)), len),
+                        None => {
+                            self.errors.push((ParseError::InvalidNumber(rest[..len].to_string()), start..start + len));
+                            (None, len)
                         }
-                    } else {
-                        tokens.push(Token::Operator(c));
-                        chars.next();
                     }
                 }
-                ' ' | '\t' | '\n' => {
-                    chars.next(); // Skip whitespace
+                c if c == self.format.argument_separator() => (Some(Token::Operator(',')), 1),
+                '+' | '-' | '*' | '/' | '%' | '^' | '(' | ')' | '[' | ']' => (Some(Token::Operator(c)), 1),
+                c if c.is_alphabetic() || c == '_' => {
+                    let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                    (Some(Token::Ident(&rest[..len])), len)
                 }
                 _ => {
-                    return Err(ParseError::InvalidCharacter(c));
+                    self.errors.push((ParseError::InvalidCharacter(c), start..start + c.len_utf8()));
+                    (None, c.len_utf8())
                 }
+            };
+            self.pos += len;
+            if let Some(token) = token {
+                return (token, start..self.pos);
             }
         }
-
-        if tokens.is_empty() {
-            return Err(ParseError::EmptyExpression);
-        }
-
-        tokens.push(Token::EOF);
-        Ok(tokens)
     }
+}
 
-    pub fn next(&mut self) -> Token {
-        if self.current_token < self.tokens.len() {
-            let token = self.tokens[self.current_token].clone();
-            self.current_token += 1;
-            token
-        } else {
-            Token::EOF
-        }
-    }
+/// The tokens up to, but not including, `Token::EOF`.
+impl<'a> Iterator for Lexer<'a> {
+    type Item = (Token<'a>, Span);
 
-    pub fn peek(&self) -> Token {
-        if self.current_token < self.tokens.len() {
-            self.tokens[self.current_token].clone()
-        } else {
-            Token::EOF
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read() {
+            (Token::EOF, _) => None,
+            token => Some(token),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(f32),
+    /// A named unknown, which only has a value once it is solved for
+    Variable(String),
+    /// An uncertain value somewhere between two bounds, written `[30..40]`
+    Range { low: Box<Expr>, high: Box<Expr> },
     UnaryOp { op: char, operand: Box<Expr> },
     BinaryOp { op: char, left: Box<Expr>, right: Box<Expr> },
+    /// One of the built-in functions, with the number of arguments already checked
+    Call { name: String, args: Vec<Expr> },
+    /// Stands in for a part that didn't parse, so the rest of a broken expression is still there
+    Invalid,
 }
 
 impl Expr {
     pub fn evaluate(&self) -> Result<f32, String> {
+        self.evaluate_with(&|_| None)
+    }
+
+    /// Evaluates with `lookup` giving the values of unknowns.
+    pub fn evaluate_with(&self, lookup: &dyn Fn(&str) -> Option<f32>) -> Result<f32, String> {
         match self {
             Expr::Number(n) => Ok(*n),
+            Expr::Variable(name) => lookup(name).ok_or_else(|| format!("Unknown value: {}", name)),
+            Expr::Invalid => Err("Invalid expression".to_string()),
+            // Where a single number is needed a range stands for its midpoint
+            Expr::Range { low, high } => Ok((low.evaluate_with(lookup)? + high.evaluate_with(lookup)?) / 2.0),
             Expr::UnaryOp { op, operand } => {
-                let val = operand.evaluate()?;
+                let val = operand.evaluate_with(lookup)?;
                 match op {
                     '+' => Ok(val),
                     '-' => Ok(-val),
@@ -173,64 +242,443 @@ impl Expr {
                 }
             }
             Expr::BinaryOp { op, left, right } => {
-                let left_val = left.evaluate()?;
-                let right_val = right.evaluate()?;
+                let left_val = left.evaluate_with(lookup)?;
+                let right_val = right.evaluate_with(lookup)?;
+                binary(*op, left_val, right_val)
+            }
+            Expr::Call { name, args } => {
+                let args = args.iter().map(|a| a.evaluate_with(lookup)).collect::<Result<Vec<_>, _>>()?;
+                call(name, &args)
+            }
+        }
+    }
+}
+
+/// Applies a binary operator to two values.
+pub fn binary(op: char, left: f32, right: f32) -> Result<f32, String> {
+    match op {
+        '+' => Ok(left + right),
+        '-' => Ok(left - right),
+        '*' => Ok(left * right),
+        '/' if right == 0.0 => Err("Division by zero".to_string()),
+        '/' => Ok(left / right),
+        '%' if right == 0.0 => Err("Modulo by zero".to_string()),
+        '%' => Ok(left % right),
+        '^' => {
+            let value = left.powf(right);
+            if value.is_nan() {
+                Err(format!("Invalid power: {}^{}", left, right))
+            } else {
+                Ok(value)
+            }
+        }
+        _ => Err(format!("Unknown binary operator: {}", op)),
+    }
+}
+
+/// A built-in function, looked up by name once so a compiled program doesn't compare names on every run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Round,
+    Floor,
+    Ceil,
+    Sqrt,
+    Clamp,
+}
+
+impl Function {
+    pub fn named(name: &str) -> Option<Function> {
+        match name {
+            "round" => Some(Function::Round),
+            "floor" => Some(Function::Floor),
+            "ceil" => Some(Function::Ceil),
+            "sqrt" => Some(Function::Sqrt),
+            "clamp" => Some(Function::Clamp),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Round => "round",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Sqrt => "sqrt",
+            Function::Clamp => "clamp",
+        }
+    }
+
+    /// Calls the function on already evaluated arguments.
+    pub fn apply(self, args: &[f32]) -> Result<f32, String> {
+        match (self, args) {
+            (Function::Round, [x]) => Ok(x.round()),
+            (Function::Floor, [x]) => Ok(x.floor()),
+            (Function::Ceil, [x]) => Ok(x.ceil()),
+            (Function::Sqrt, [x]) if *x < 0.0 => Err(format!("Square root of negative number: {}", x)),
+            (Function::Sqrt, [x]) => Ok(x.sqrt()),
+            (Function::Clamp, [_, low, high]) if low > high => Err(format!("clamp bounds out of order: {} > {}", low, high)),
+            (Function::Clamp, [x, low, high]) => Ok(x.max(*low).min(*high)),
+            _ => Err(format!("Unknown function: {}", self.name())),
+        }
+    }
+}
+
+/// Calls a built-in function on already evaluated arguments.
+pub fn call(name: &str, args: &[f32]) -> Result<f32, String> {
+    match Function::named(name) {
+        Some(function) => function.apply(args),
+        None => Err(format!("Unknown function: {}", name)),
+    }
+}
+
+impl Expr {
+    /// Names of the unknowns, in order of first appearance.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Number(_) | Expr::Invalid => {}
+            Expr::Variable(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Expr::UnaryOp { operand, .. } => operand.collect_variables(names),
+            Expr::BinaryOp { left, right, .. } | Expr::Range { low: left, high: right } => {
+                left.collect_variables(names);
+                right.collect_variables(names);
+            }
+            Expr::Call { args, .. } => args.iter().for_each(|a| a.collect_variables(names)),
+        }
+    }
+
+    pub fn has_range(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Variable(_) | Expr::Invalid => false,
+            Expr::Range { .. } => true,
+            Expr::UnaryOp { operand, .. } => operand.has_range(),
+            Expr::BinaryOp { left, right, .. } => left.has_range() || right.has_range(),
+            Expr::Call { args, .. } => args.iter().any(Expr::has_range),
+        }
+    }
+
+    /// Evaluates with interval arithmetic, giving the lowest and highest possible value.
+    pub fn evaluate_interval(&self) -> Result<Interval, String> {
+        match self {
+            Expr::Number(n) => Ok(Interval::exact(*n)),
+            Expr::Variable(name) => Err(format!("Unknown value: {}", name)),
+            Expr::Invalid => self.evaluate().map(Interval::exact),
+            Expr::Range { low, high } => {
+                let (low, high) = (low.evaluate_interval()?, high.evaluate_interval()?);
+                Ok(Interval { low: low.low.min(high.low), high: low.high.max(high.high) })
+            }
+            Expr::UnaryOp { op: '-', operand } => {
+                let v = operand.evaluate_interval()?;
+                Ok(Interval { low: -v.high, high: -v.low })
+            }
+            Expr::UnaryOp { operand, .. } => operand.evaluate_interval(),
+            Expr::BinaryOp { op, left, right } => {
+                let (a, b) = (left.evaluate_interval()?, right.evaluate_interval()?);
                 match op {
-                    '+' => Ok(left_val + right_val),
-                    '-' => Ok(left_val - right_val),
-                    '*' => Ok(left_val * right_val),
-                    '/' => {
-                        if right_val == 0.0 {
-                            Err("Division by zero".to_string())
-                        } else {
-                            Ok(left_val / right_val)
-                        }
+                    '+' => Ok(Interval { low: a.low + b.low, high: a.high + b.high }),
+                    '-' => Ok(Interval { low: a.low - b.high, high: a.high - b.low }),
+                    '*' => Ok(Interval::spanning([a.low * b.low, a.low * b.high, a.high * b.low, a.high * b.high])),
+                    '/' if b.low <= 0.0 && b.high >= 0.0 => Err("Division by zero".to_string()),
+                    '/' => Ok(Interval::spanning([a.low / b.low, a.low / b.high, a.high / b.low, a.high / b.high])),
+                    // Powers of a non-negative base are monotonic in both arguments
+                    '^' if a.low >= 0.0 => {
+                        Ok(Interval::spanning([a.low.powf(b.low), a.low.powf(b.high), a.high.powf(b.low), a.high.powf(b.high)]))
                     }
-                    _ => Err(format!("Unknown binary operator: {}", op)),
+                    '%' | '^' if a.low == a.high && b.low == b.high => Ok(Interval::exact(self.evaluate()?)),
+                    _ => Err(format!("Ranges can't be used with '{}' here", op)),
                 }
             }
+            Expr::Call { name, args } => {
+                let args = args.iter().map(Expr::evaluate_interval).collect::<Result<Vec<_>, _>>()?;
+                // Every function is non-decreasing in each argument, so the bounds map to bounds
+                let lows: Vec<f32> = args.iter().map(|a| a.low).collect();
+                let highs: Vec<f32> = args.iter().map(|a| a.high).collect();
+                let (low, high) = (call(name, &lows)?, call(name, &highs)?);
+                Ok(Interval { low: low.min(high), high: low.max(high) })
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluates one step at a time. Each step works out every operation whose operands are
+    /// already numbers, so the result runs from the expression itself down to its value.
+    pub fn trace(&self) -> Result<Vec<Expr>, String> {
+        let mut steps = vec![self.clone()];
+        while let Some(last) = steps.last_mut()
+            && !matches!(last, Expr::Number(_))
+        {
+            let next = last.reduce()?;
+            // A sign applied to a number prints the same before and after
+            if next.to_string() == last.to_string() {
+                *last = next;
+            } else {
+                steps.push(next);
+            }
+        }
+        Ok(steps)
+    }
+
+    fn reduce(&self) -> Result<Expr, String> {
+        let number = |e: &Expr| matches!(e, Expr::Number(_));
+        let ready = match self {
+            Expr::Number(_) | Expr::Variable(_) | Expr::Invalid => true,
+            Expr::UnaryOp { operand, .. } => number(operand),
+            Expr::BinaryOp { left, right, .. } | Expr::Range { low: left, high: right } => number(left) && number(right),
+            Expr::Call { args, .. } => args.iter().all(number),
+        };
+        if ready {
+            return self.evaluate().map(Expr::Number);
+        }
+        let reduce = |e: &Expr| e.reduce().map(Box::new);
+        Ok(match self {
+            Expr::UnaryOp { op, operand } => Expr::UnaryOp { op: *op, operand: reduce(operand)? },
+            Expr::BinaryOp { op, left, right } => Expr::BinaryOp { op: *op, left: reduce(left)?, right: reduce(right)? },
+            Expr::Range { low, high } => Expr::Range { low: reduce(low)?, high: reduce(high)? },
+            Expr::Call { name, args } => Expr::Call {
+                name: name.clone(),
+                args: args.iter().map(Expr::reduce).collect::<Result<_, _>>()?,
+            },
+            Expr::Number(_) | Expr::Variable(_) | Expr::Invalid => self.clone(),
+        })
+    }
+
+    // How tightly the expression holds together when printed next to an operator
+    fn binding_power(&self) -> u8 {
+        match self {
+            Expr::UnaryOp { op, .. } => Parser::prefix_binding_power(*op).1,
+            // A negative result of a reduction reads like a sign
+            Expr::Number(n) if *n < 0.0 => Parser::prefix_binding_power('-').1,
+            Expr::BinaryOp { op, .. } => Parser::infix_binding_power(*op).map_or(0, |(left, _)| left),
+            Expr::Number(_) | Expr::Variable(_) | Expr::Range { .. } | Expr::Call { .. } | Expr::Invalid => u8::MAX,
+        }
+    }
+}
+
+fn wrap(text: String, parens: bool) -> String {
+    if parens { format!("({})", text) } else { text }
+}
+
+/// Prints the expression with canonical spacing and only the parentheses the parser needs.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Invalid => write!(f, "?"),
+            Expr::Range { low, high } => write!(f, "[{}..{}]", low, high),
+            Expr::Call { name, args } => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::UnaryOp { op, operand } => {
+                let (_, bp) = Parser::prefix_binding_power(*op);
+                write!(f, "{}{}", op, wrap(operand.to_string(), operand.binding_power() < bp))
+            }
+            Expr::BinaryOp { op, left, right } => {
+                let (left_bp, right_bp) = Parser::infix_binding_power(*op).unwrap_or((0, 0));
+                // The left side stays together only if this operator couldn't have continued it
+                let left_parens = match left.as_ref() {
+                    Expr::BinaryOp { op, .. } => Parser::infix_binding_power(*op).is_some_and(|(_, r)| r <= left_bp),
+                    _ => left.binding_power() <= left_bp,
+                };
+                // A sign reads fine on the right, except straight after `+` or `-` where the parser calls it a typo
+                let text = right.to_string();
+                let right_parens = (matches!(right.as_ref(), Expr::BinaryOp { .. }) && right.binding_power() < right_bp)
+                    || (matches!(op, '+' | '-') && text.starts_with(['+', '-']));
+                write!(f, "{} {} {}", wrap(left.to_string(), left_parens), op, wrap(text, right_parens))
+            }
         }
     }
 }
 
-pub struct Parser {
-    lexer: Lexer,
+/// The bounds of a value that is only known to lie in a range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub low: f32,
+    pub high: f32,
 }
 
-impl Parser {
-    pub fn new(expr: &str) -> Result<Parser, ParseError> {
-        let lexer = Lexer::new(expr)?;
-        Ok(Parser { lexer })
+impl Interval {
+    pub fn exact(value: f32) -> Self {
+        Self { low: value, high: value }
+    }
+
+    fn spanning(values: [f32; 4]) -> Self {
+        Self {
+            low: values.iter().copied().fold(f32::INFINITY, f32::min),
+            high: values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+}
+
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    // The next token, read ahead so it can be peeked at
+    peeked: (Token<'a>, Span),
+    last_span: Span,
+    // Every sub-expression parsed so far with its span, kept only when recovering for the Marks field
+    nodes: Vec<(Span, Expr)>,
+    error_span: Span,
+    // Whether to note problems in `diagnostics` and carry on rather than fail at the first
+    recover: bool,
+    diagnostics: Vec<Diagnostic>,
+    multiplication: Multiplication,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(expr: &'a str, format: NumberFormat, options: ParseOptions) -> Parser<'a> {
+        Parser::with_recovery(expr, format, options, false)
+    }
+
+    fn with_recovery(expr: &'a str, format: NumberFormat, options: ParseOptions, recover: bool) -> Parser<'a> {
+        let mut lexer = Lexer::new(expr, format);
+        let peeked = lexer.read();
+        Parser {
+            lexer,
+            peeked,
+            last_span: 0..0,
+            nodes: Vec::new(),
+            error_span: 0..0,
+            recover,
+            diagnostics: Vec::new(),
+            multiplication: options.multiplication,
+        }
     }
 
     pub fn parse(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.parse_expression(0)?;
-        
-        // Make sure we consumed all tokens
-        match self.lexer.peek() {
-            Token::EOF => Ok(expr),
-            token => Err(ParseError::UnexpectedToken(format!("{:?}", token))),
+        let result = self.parse_all();
+        // The lexer only reads ahead of the parser, so whichever problem comes first in the text is reported
+        if !self.recover
+            && !self.lexer.errors.is_empty()
+            && (result.is_ok() || self.lexer.errors[0].1.start <= self.error_span.start)
+        {
+            let (error, span) = self.lexer.errors.remove(0);
+            return Err(self.fail_at(span, error));
+        }
+        result
+    }
+
+    fn parse_all(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Token::EOF && self.lexer.errors.is_empty() {
+            return Err(self.fail_at(0..self.lexer.text.len(), ParseError::EmptyExpression));
         }
+        let (mut expr, _) = self.parse_expression(0)?;
+
+        // Make sure we consumed all tokens. When recovering, a stray token is skipped
+        // and whatever operators follow it carry on from the expression so far.
+        while self.peek() != Token::EOF {
+            let token = self.peek();
+            self.report(self.peek_span(), ParseError::UnexpectedToken(format!("{:?}", token)))?;
+            self.next();
+            let span = self.last_span();
+            (expr, _) = self.parse_operators((expr, span), 0)?;
+        }
+        Ok(expr)
     }
 
-    fn parse_expression(&mut self, min_bp: u8) -> Result<Expr, ParseError> {
-        let mut left = self.parse_prefix()?;
+    fn peek(&self) -> Token<'a> {
+        self.peeked.0
+    }
+
+    // Span of the token `peek` would return
+    fn peek_span(&self) -> Span {
+        self.peeked.1.clone()
+    }
+
+    fn next(&mut self) -> Token<'a> {
+        let (token, span) = std::mem::replace(&mut self.peeked, self.lexer.read());
+        self.last_span = span;
+        token
+    }
 
+    // Span of the token `next` returned last
+    fn last_span(&self) -> Span {
+        self.last_span.clone()
+    }
+
+    fn fail_at(&mut self, span: Span, error: ParseError) -> ParseError {
+        self.error_span = span;
+        error
+    }
+
+    // Fails like `fail_at`, or when recovering notes the problem once and lets parsing go on
+    fn report(&mut self, span: Span, error: ParseError) -> Result<(), ParseError> {
+        if !self.recover {
+            return Err(self.fail_at(span, error));
+        }
+        if !self.diagnostics.iter().any(|(_, s)| *s == span) {
+            self.diagnostics.push((error, span));
+        }
+        Ok(())
+    }
+
+    // The same sign twice in a row, as in `2 + + 3` or `2 --3`, is a key pressed twice in a marks field,
+    // however it is spaced. A different sign, as in `2 + -3`, is the sign of the operand.
+    fn reject_repeated_sign(&mut self, op: char) -> Result<(), ParseError> {
+        if matches!(op, '+' | '-') && self.peek() == Token::Operator(op) {
+            let span = self.peek_span();
+            self.report(span, ParseError::UnexpectedToken(format!("operator '{}'", op)))?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, expr: Expr, span: Span) -> (Expr, Span) {
+        if self.recover {
+            self.nodes.push((span.clone(), expr.clone()));
+        }
+        (expr, span)
+    }
+
+    fn parse_expression(&mut self, min_bp: u8) -> Result<(Expr, Span), ParseError> {
+        let left = self.parse_prefix()?;
+        self.parse_operators(left, min_bp)
+    }
+
+    // Applies the infix operators that follow `left` and bind at least as tightly as `min_bp`
+    fn parse_operators(&mut self, mut left: (Expr, Span), min_bp: u8) -> Result<(Expr, Span), ParseError> {
         loop {
-            let token = self.lexer.peek();
+            let token = self.peek();
             
             // Check if we should continue parsing
             let bp = match &token {
-                Token::Operator(op) => self.infix_binding_power(*op),
+                Token::Operator(op) => Parser::infix_binding_power(*op),
                 _ => None,
             };
 
             match bp {
                 Some((left_bp, _)) if left_bp >= min_bp => {
                     // Continue parsing
-                    self.lexer.next(); // Consume the operator
+                    self.next(); // Consume the operator
                     left = self.parse_infix(left, token)?;
                 }
+                // An operand straight after another, as in `2(3)`, `(1)(2)`, `2x` or `2 3`, multiplies it
+                None if Parser::starts_operand(&token) => {
+                    let (left_bp, right_bp) = Parser::IMPLICIT_BINDING_POWER;
+                    if left_bp < min_bp {
+                        break;
+                    }
+                    if self.multiplication == Multiplication::Explicit {
+                        self.report(self.peek_span(), ParseError::MissingOperator)?;
+                    }
+                    let (left_expr, left_span) = left;
+                    let (right, right_span) = self.parse_expression(right_bp)?;
+                    let expr = Expr::BinaryOp {
+                        op: '*',
+                        left: Box::new(left_expr),
+                        right: Box::new(right),
+                    };
+                    left = self.node(expr, left_span.start..right_span.end);
+                }
                 _ => break,
             }
         }
@@ -238,76 +686,318 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_prefix(&mut self) -> Result<Expr, ParseError> {
-        let token = self.lexer.next();
+    fn parse_prefix(&mut self) -> Result<(Expr, Span), ParseError> {
+        // A stray operator is left for whatever comes next, e.g. to apply a dangling `*` to the placeholder
+        if let Token::Operator(op) = self.peek()
+            && !matches!(op, '+' | '-' | '[' | '(')
+        {
+            let span = self.peek_span();
+            self.report(span.clone(), ParseError::UnexpectedToken(format!("operator '{}'", op)))?;
+            return Ok((Expr::Invalid, span.start..span.start));
+        }
+        let token = self.next();
+        let span = self.last_span();
         
         match token {
-            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Number(n) => Ok(self.node(Expr::Number(n), span)),
+            Token::Ident(name) if self.peek() == Token::Operator('(') => self.parse_call(name, span),
+            Token::Ident(name) => Ok(self.node(Expr::Variable(name.to_string()), span)),
+            Token::Range => {
+                self.report(span.clone(), ParseError::UnexpectedToken("'..' outside a range".to_string()))?;
+                Ok((Expr::Invalid, span))
+            }
             Token::Operator(op) => match op {
                 '+' | '-' => {
-                    let (_, right_bp) = self.prefix_binding_power(op);
-                    let operand = self.parse_expression(right_bp)?;
-                    Ok(Expr::UnaryOp {
+                    let (_, right_bp) = Parser::prefix_binding_power(op);
+                    let (operand, operand_span) = self.parse_expression(right_bp)?;
+                    let expr = Expr::UnaryOp {
                         op,
                         operand: Box::new(operand),
-                    })
+                    };
+                    Ok(self.node(expr, span.start..operand_span.end))
+                }
+                '[' => {
+                    let (low, _) = self.parse_expression(0)?;
+                    if self.peek() != Token::Range {
+                        self.report(self.peek_span(), ParseError::UnexpectedToken("expected '..' in a range".to_string()))?;
+                        if self.peek() == Token::Operator(']') {
+                            self.next();
+                        }
+                        return Ok((Expr::Invalid, span.start..self.last_span().end));
+                    }
+                    self.next();
+                    let (high, _) = self.parse_expression(0)?;
+                    let expr = Expr::Range { low: Box::new(low), high: Box::new(high) };
+                    if self.peek() != Token::Operator(']') {
+                        let end = self.peek_span().end.max(span.end);
+                        self.report(span.start..end, ParseError::UnexpectedToken("expected ']' to close the range".to_string()))?;
+                        return Ok(self.node(expr, span.start..self.last_span().end));
+                    }
+                    self.next();
+                    Ok(self.node(expr, span.start..self.last_span().end))
                 }
                 '(' => {
-                    let expr = self.parse_expression(0)?;
-                    match self.lexer.next() {
-                        Token::Operator(')') => Ok(expr),
-                        _ => Err(ParseError::MissingClosingParenthesis),
+                    let (expr, _) = self.parse_expression(0)?;
+                    if self.peek() != Token::Operator(')') {
+                        // Underline from the unmatched parenthesis to where the closing one was expected
+                        let end = self.peek_span().end.max(span.end);
+                        self.report(span.start..end, ParseError::MissingClosingParenthesis)?;
+                        return Ok(self.node(expr, span.start..self.last_span().end));
                     }
+                    self.next();
+                    Ok(self.node(expr, span.start..self.last_span().end))
                 }
-                _ => Err(ParseError::UnexpectedToken(format!("operator '{}'", op))),
+                _ => unreachable!("stray operators are handled above"),
             },
-            Token::EOF => Err(ParseError::UnexpectedEndOfExpression),
+            Token::EOF => {
+                self.report(span.clone(), ParseError::UnexpectedEndOfExpression)?;
+                Ok((Expr::Invalid, span))
+            }
+        }
+    }
+
+    // Arguments of a call, from the `(` after the function name to the closing `)`
+    fn parse_call(&mut self, name: &str, name_span: Span) -> Result<(Expr, Span), ParseError> {
+        let expected = arity(name);
+        if expected.is_none() {
+            self.report(name_span.clone(), ParseError::UnknownFunction(name.to_string()))?;
+        }
+        let open = self.peek_span();
+        self.next();
+        let mut args = Vec::new();
+        if self.peek() != Token::Operator(')') {
+            loop {
+                let (arg, _) = self.parse_expression(0)?;
+                args.push(arg);
+                if self.peek() != Token::Operator(',') {
+                    break;
+                }
+                self.next();
+            }
+        }
+        if self.peek() == Token::Operator(')') {
+            self.next();
+        } else {
+            let end = self.peek_span().end.max(open.end);
+            self.report(open.start..end, ParseError::MissingClosingParenthesis)?;
+        }
+        let span = name_span.start..self.last_span().end;
+        match expected {
+            Some(expected) if args.len() == expected => Ok(self.node(Expr::Call { name: name.to_string(), args }, span)),
+            Some(expected) => {
+                self.report(span.clone(), ParseError::ArgumentCount { name: name.to_string(), expected, found: args.len() })?;
+                Ok((Expr::Invalid, span))
+            }
+            None => Ok((Expr::Invalid, span)),
         }
     }
 
-    fn parse_infix(&mut self, left: Expr, op_token: Token) -> Result<Expr, ParseError> {
+    fn parse_infix(&mut self, (left, left_span): (Expr, Span), op_token: Token<'a>) -> Result<(Expr, Span), ParseError> {
+        let op_span = self.last_span();
         match op_token {
             Token::Operator(op) => {
-                let (_, right_bp) = self.infix_binding_power(op)
-                    .ok_or_else(|| ParseError::UnexpectedToken(format!("operator '{}'", op)))?;
-                
-                let right = self.parse_expression(right_bp)?;
+                let (_, right_bp) = match Parser::infix_binding_power(op) {
+                    Some(bp) => bp,
+                    None => return Err(self.fail_at(op_span, ParseError::UnexpectedToken(format!("operator '{}'", op)))),
+                };
+
+                self.reject_repeated_sign(op)?;
+
+                let (right, right_span) = self.parse_expression(right_bp)?;
                 
-                Ok(Expr::BinaryOp {
+                let expr = Expr::BinaryOp {
                     op,
                     left: Box::new(left),
                     right: Box::new(right),
-                })
+                };
+                Ok(self.node(expr, left_span.start..right_span.end))
             }
-            _ => Err(ParseError::UnexpectedToken(format!("{:?}", op_token))),
+            _ => Err(self.fail_at(op_span, ParseError::UnexpectedToken(format!("{:?}", op_token)))),
         }
     }
 
-    fn prefix_binding_power(&self, op: char) -> ((), u8) {
+    // Implicit multiplication binds like `*`
+    const IMPLICIT_BINDING_POWER: (u8, u8) = (7, 8);
+
+    fn starts_operand(token: &Token<'a>) -> bool {
+        matches!(token, Token::Number(_) | Token::Ident(_) | Token::Operator('(' | '['))
+    }
+
+    fn prefix_binding_power(op: char) -> ((), u8) {
         match op {
             '+' | '-' => ((), 9),
             _ => ((), 0),
         }
     }
 
-    fn infix_binding_power(&self, op: char) -> Option<(u8, u8)> {
+    fn infix_binding_power(op: char) -> Option<(u8, u8)> {
         let res = match op {
             '+' | '-' => (5, 6),  // Left associative
-            '*' | '/' => (7, 8),  // Left associative, higher precedence
+            '*' | '/' | '%' => (7, 8),  // Left associative, higher precedence
+            '^' => (12, 11),  // Right associative, binds tighter than unary minus
             _ => return None,
         };
         Some(res)
     }
 }
 
+/// What a token in an `Analysis` is, without borrowing the text it was read from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Number,
+    Operator(char),
+    Function,
+    /// A name that isn't a function, such as `x`
+    Unknown,
+    Range,
+}
+
+impl TokenKind {
+    fn of(token: Token) -> TokenKind {
+        match token {
+            Token::Number(_) => TokenKind::Number,
+            Token::Operator(op) => TokenKind::Operator(op),
+            Token::Ident(name) if is_function(name) => TokenKind::Function,
+            Token::Ident(_) => TokenKind::Unknown,
+            Token::Range => TokenKind::Range,
+            Token::EOF => unreachable!("the lexer stops at the end marker"),
+        }
+    }
+}
+
+/// Everything the Marks field needs to highlight an expression, even one that doesn't parse.
+/// It owns all of it, so a row can keep it for as long as its marks stay the same.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    /// Every token that could be read, without the end marker
+    pub tokens: Vec<(TokenKind, Span)>,
+    /// Sub-expressions with their spans, innermost first
+    pub nodes: Vec<(Span, Expr)>,
+    /// As much of the expression as parsed, with `Expr::Invalid` where parts are broken
+    pub expr: Option<Expr>,
+    /// Every problem found, in the order they appear in the text
+    pub errors: Vec<(String, Span)>,
+    // Whether the text parsed without problems, so that `errors` can only be about evaluating it
+    parsed: bool,
+}
+
+impl Analysis {
+    #[cfg(test)]
+    pub fn new(expr: &str) -> Self {
+        Analysis::new_in(expr, NumberFormat::default(), ParseOptions::default())
+    }
+
+    pub fn new_in(expr: &str, format: NumberFormat, options: ParseOptions) -> Self {
+        let mut analysis = Analysis::default();
+        let mut lexer = Lexer::new(expr, format);
+        analysis.tokens = lexer.by_ref().map(|(token, span)| (TokenKind::of(token), span)).collect();
+        let mut errors = lexer.errors;
+        if analysis.tokens.is_empty() {
+            if errors.is_empty() {
+                errors.push((ParseError::EmptyExpression, 0..expr.len()));
+            }
+        } else {
+            // The parser reads the text again and meets the same lexer errors, which are already in `errors`
+            let mut parser = Parser::with_recovery(expr, format, options, true);
+            match parser.parse() {
+                Ok(parsed) => analysis.expr = Some(parsed),
+                Err(e) => parser.diagnostics.push((e, parser.error_span.clone())),
+            }
+            errors.append(&mut parser.diagnostics);
+            analysis.nodes = parser.nodes;
+        }
+        analysis.errors = errors.into_iter().map(|(e, span)| (e.to_string(), span)).collect();
+        analysis.errors.sort_by_key(|(_, span)| span.start);
+        analysis.parsed = analysis.errors.is_empty();
+
+        // Children are recorded before their parents, so the first failure is the innermost one.
+        // Parts with unknowns are left alone, they only have a value once solved for.
+        if analysis.errors.is_empty() {
+            analysis.errors = analysis
+                .nodes
+                .iter()
+                .filter(|(_, e)| e.variables().is_empty())
+                .find_map(|(span, e)| e.evaluate().err().map(|e| (e, span.clone())))
+                .into_iter()
+                .collect();
+        }
+        analysis
+    }
+
+    /// The expression, if the text parsed without problems, as `Calculator::parse_in` would return it.
+    /// It may still fail to evaluate.
+    pub fn parsed(&self) -> Option<&Expr> {
+        self.expr.as_ref().filter(|_| self.parsed)
+    }
+
+    /// The smallest sub-expression whose span contains `pos`.
+    pub fn node_at(&self, pos: usize) -> Option<&(Span, Expr)> {
+        self.nodes
+            .iter()
+            .filter(|(span, _)| span.start <= pos && pos < span.end)
+            .min_by_key(|(span, _)| span.len())
+    }
+
+    /// Spans of the parenthesis or range bracket next to `pos` and the one that matches it.
+    pub fn matching_paren(&self, pos: usize) -> Option<(Span, Span)> {
+        let (_, kind) = self.tokens.iter().find_map(|(t, span)| match t {
+            TokenKind::Operator(c @ ('(' | ')' | '[' | ']')) if span.start == pos || span.end == pos => Some((span, *c)),
+            _ => None,
+        })?;
+        let pair = if matches!(kind, '(' | ')') { ('(', ')') } else { ('[', ']') };
+        let parens: Vec<(char, &Span)> = self
+            .tokens
+            .iter()
+            .filter_map(|(t, span)| match t {
+                TokenKind::Operator(c) if *c == pair.0 || *c == pair.1 => Some((*c, span)),
+                _ => None,
+            })
+            .collect();
+        let (at, _) = parens
+            .iter()
+            .enumerate()
+            .find(|(_, (c, span))| *c == kind && (span.start == pos || span.end == pos))?;
+        let (open, _) = parens[at];
+        let step: isize = if open == pair.0 { 1 } else { -1 };
+        let mut depth = 0;
+        let mut i = at as isize;
+        while i >= 0 && (i as usize) < parens.len() {
+            let (c, span) = parens[i as usize];
+            depth += if c == open { 1 } else { -1 };
+            if depth == 0 {
+                return Some((parens[at].1.clone(), span.clone()));
+            }
+            i += step;
+        }
+        None
+    }
+}
+
 pub struct Calculator {}
 
 impl Calculator {
+    #[cfg(test)]
+    pub fn parse(expr: &str) -> Result<Expr, String> {
+        Calculator::parse_in(expr, NumberFormat::default(), ParseOptions::default())
+    }
+
+    /// Parses with numbers written as `format` writes them.
+    pub fn parse_in(expr: &str, format: NumberFormat, options: ParseOptions) -> Result<Expr, String> {
+        Parser::new(expr, format, options).parse().map_err(|e| e.to_string())
+    }
+
+    /// Rewrites the expression with canonical spacing and parentheses, keeping `format`'s numbers.
+    pub fn canonical_in(expr: &str, format: NumberFormat, options: ParseOptions) -> Result<String, String> {
+        let printed = Calculator::parse_in(expr, format, options)?.to_string();
+        Ok(NumberFormat::default().convert(&printed, format))
+    }
+
     pub fn str_2_f(expr: &str) -> Result<f32, String> {
-        let mut parser = Parser::new(expr)
-            .map_err(|e| e.to_string())?;
-        
-        let ast = parser.parse()
+        Calculator::str_2_f_in(expr, NumberFormat::default(), ParseOptions::default())
+    }
+
+    pub fn str_2_f_in(expr: &str, format: NumberFormat, options: ParseOptions) -> Result<f32, String> {
+        let ast = Parser::new(expr, format, options).parse()
             .map_err(|e| e.to_string())?;
         
         ast.evaluate()
@@ -327,23 +1017,19 @@ impl Calculator {
     }
 }
 
-// Helper function to parse and print AST
-pub fn print_parsed(expr: &str) {
-    match Parser::new(expr) {
-        Ok(mut parser) => {
-            match parser.parse() {
-                Ok(ast) => {
-                    println!("Expression: {}", expr);
-                    println!("Parsed AST: {:#?}", ast);
-                    match ast.evaluate() {
-                        Ok(result) => println!("Result: {}", result),
-                        Err(e) => println!("Evaluation error: {}", e),
-                    }
-                }
-                Err(e) => println!("Parse error: {}", e),
+// Helper function to parse and print AST, with numbers written as `format` writes them
+pub fn print_parsed(expr: &str, format: NumberFormat, options: ParseOptions) {
+    match Calculator::parse_in(expr, format, options) {
+        Ok(ast) => {
+            println!("Expression: {}", expr);
+            println!("Parsed AST: {:#?}", ast);
+            println!("Canonical: {}", NumberFormat::default().convert(&ast.to_string(), format));
+            match ast.evaluate() {
+                Ok(result) => println!("Result: {}", NumberFormat::default().convert(&result.to_string(), format)),
+                Err(e) => println!("Evaluation error: {}", e),
             }
         }
-        Err(e) => println!("Lexer error: {}", e),
+        Err(e) => println!("Parse error: {}", e),
     }
     println!();
 }
@@ -351,6 +1037,8 @@ pub fn print_parsed(expr: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::number_format::DecimalSeparator;
+    use crate::test_util::close;
 
     #[test]
     fn test_simple_arithmetic() {
@@ -367,6 +1055,147 @@ mod tests {
         assert_eq!(Calculator::str_2_f("10 - 6 / 2").unwrap(), 7.0);
     }
 
+    #[test]
+    fn test_power_precedence() {
+        assert_eq!(Calculator::str_2_f("2 * 3 ^ 2").unwrap(), 18.0);
+        assert_eq!(Calculator::str_2_f("(2 * 3) ^ 2").unwrap(), 36.0);
+        assert_eq!(Calculator::str_2_f("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(Calculator::str_2_f("(2 ^ 3) ^ 2").unwrap(), 64.0);
+        assert_eq!(Calculator::str_2_f("-2 ^ 2").unwrap(), -4.0);
+        assert_eq!(Calculator::str_2_f("(-2) ^ 2").unwrap(), 4.0);
+        assert_eq!(Calculator::str_2_f("2 ^ -1").unwrap(), 0.5);
+        assert!(Calculator::str_2_f("(-8) ^ 0.5").is_err());
+    }
+
+    #[test]
+    fn test_modulo_precedence() {
+        assert_eq!(Calculator::str_2_f("2 + 7 % 4").unwrap(), 5.0);
+        assert_eq!(Calculator::str_2_f("(2 + 7) % 4").unwrap(), 1.0);
+        assert_eq!(Calculator::str_2_f("10 % 4 * 3").unwrap(), 6.0);
+        assert_eq!(Calculator::str_2_f("10 % (4 * 3)").unwrap(), 10.0);
+        assert_eq!(Calculator::str_2_f("2 ^ 3 % 5").unwrap(), 3.0);
+        assert!(Calculator::str_2_f("5 % 0").is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(Calculator::str_2_f("round(2.5) + floor(2.7) * 2").unwrap(), 7.0);
+        assert_eq!(Calculator::str_2_f("ceil(2.1) ^ 2").unwrap(), 9.0);
+        assert_eq!(Calculator::str_2_f("-sqrt(16)").unwrap(), -4.0);
+        assert_eq!(Calculator::str_2_f("10 * sqrt(81) / 100").unwrap(), 0.9);
+        assert_eq!(Calculator::str_2_f("clamp(105, 0, 100)").unwrap(), 100.0);
+        assert_eq!(Calculator::str_2_f("clamp(45 + 10, 2 * 30, 100) / 100").unwrap(), 0.6);
+        assert!(Calculator::str_2_f("sqrt(-1)").is_err());
+        assert!(Calculator::str_2_f("clamp(5, 10, 0)").is_err());
+        assert_eq!(Calculator::str_2_f("clamp(1, 2)").unwrap_err(), "clamp takes 3 arguments, found 2");
+        assert_eq!(Calculator::str_2_f("cube(2)").unwrap_err(), "Unknown function: cube");
+        assert!(Calculator::str_2_f("round(2").is_err());
+        assert_eq!(Calculator::parse("round(x) + sqrt").unwrap().variables(), ["x", "sqrt"]);
+        let interval = Calculator::parse("sqrt([64..81]) / 10").unwrap().evaluate_interval().unwrap();
+        assert_eq!(interval, Interval { low: 0.8, high: 0.9 });
+    }
+
+    #[test]
+    fn test_display() {
+        let print = |s: &str| Calculator::parse(s).unwrap().to_string();
+        assert_eq!(print("(25+10 + 76)/(50+20+80)"), "(25 + 10 + 76) / (50 + 20 + 80)");
+        assert_eq!(print("((2 * 3)) + 4"), "2 * 3 + 4");
+        assert_eq!(print("10 - (6 - 2)"), "10 - (6 - 2)");
+        assert_eq!(print("(10 - 6) - 2"), "10 - 6 - 2");
+        assert_eq!(print("2 ^ (3 ^ 2)"), "2 ^ 3 ^ 2");
+        assert_eq!(print("(2 ^ 3) ^ 2"), "(2 ^ 3) ^ 2");
+        assert_eq!(print("-(2 ^ 2)"), "-2 ^ 2");
+        assert_eq!(print("(-2) ^ 2"), "(-2) ^ 2");
+        assert_eq!(print("-(2 * 3)"), "-(2 * 3)");
+        assert_eq!(print("2 * (-3)"), "2 * -3");
+        assert_eq!(print("2 - (-3 * x)"), "2 - (-3 * x)");
+        assert_eq!(print("3(4)"), "3 * 4");
+        assert_eq!(print("clamp( x,0 ,[1.5 .. 2] )"), "clamp(x, 0, [1.5..2])");
+        let comma = NumberFormat { decimal: DecimalSeparator::Comma };
+        let canonical = Calculator::canonical_in("clamp(87,5;0;  1e2)/100", comma, ParseOptions::default());
+        assert_eq!(canonical.unwrap(), "clamp(87,5; 0; 100) / 100");
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = |s: &str| -> Vec<String> {
+            Calculator::parse(s).unwrap().trace().unwrap().iter().map(Expr::to_string).collect()
+        };
+        assert_eq!(trace("(25+10)/(50+20)"), ["(25 + 10) / (50 + 20)", "35 / 70", "0.5"]);
+        assert_eq!(trace("45/50"), ["45 / 50", "0.9"]);
+        assert_eq!(trace("7"), ["7"]);
+        assert_eq!(trace("2 * -(1 + 2) ^ 2"), ["2 * -(1 + 2) ^ 2", "2 * -3 ^ 2", "2 * -9", "-18"]);
+        assert_eq!(trace("(0 - 3) ^ 2"), ["(0 - 3) ^ 2", "(-3) ^ 2", "9"]);
+        assert_eq!(trace("1 - (0 - 3)"), ["1 - (0 - 3)", "1 - (-3)", "4"]);
+        assert_eq!(trace("clamp(2 * 60, 0, 100) / [40..60]"), ["clamp(2 * 60, 0, 100) / [40..60]", "clamp(120, 0, 100) / 50", "100 / 50", "2"]);
+        assert_eq!(Calculator::parse("(1 + 2) / x").unwrap().trace().unwrap_err(), "Unknown value: x");
+    }
+
+    // Small xorshift generator, so the property tests are repeatable without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn expr(&mut self, depth: u32) -> Expr {
+            let leaf = depth == 0 || self.below(4) == 0;
+            match if leaf { self.below(2) } else { 2 + self.below(4) } {
+                0 => Expr::Number(self.below(100_000) as f32 / 100.0),
+                1 => Expr::Variable(["x", "y", "total"][self.below(3) as usize].to_string()),
+                2 => Expr::UnaryOp { op: ['-', '+'][self.below(2) as usize], operand: Box::new(self.expr(depth - 1)) },
+                3 => Expr::Range { low: Box::new(self.expr(depth - 1)), high: Box::new(self.expr(depth - 1)) },
+                4 => {
+                    let (name, arity) = FUNCTIONS[self.below(FUNCTIONS.len() as u64) as usize];
+                    Expr::Call { name: name.to_string(), args: (0..arity).map(|_| self.expr(depth - 1)).collect() }
+                }
+                _ => Expr::BinaryOp {
+                    op: ['+', '-', '*', '/', '%', '^'][self.below(6) as usize],
+                    left: Box::new(self.expr(depth - 1)),
+                    right: Box::new(self.expr(depth - 1)),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_parse_roundtrip() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..2000 {
+            let expr = rng.expr(5);
+            let printed = expr.to_string();
+            let parsed = Calculator::parse(&printed).unwrap_or_else(|e| panic!("{} does not parse: {}", printed, e));
+            assert_eq!(parsed, expr, "{}", printed);
+            // Printing is canonical, so a second round changes nothing
+            assert_eq!(parsed.to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn test_number_formats() {
+        let comma = NumberFormat { decimal: DecimalSeparator::Comma };
+        let in_comma = |expr: &str| Calculator::str_2_f_in(expr, comma, ParseOptions::default());
+        assert_eq!(Calculator::str_2_f("1e2 / 2E+2").unwrap(), 0.5);
+        assert_eq!(Calculator::str_2_f("1_000 / 2_000").unwrap(), 0.5);
+        assert_eq!(Calculator::str_2_f("2e-1 * 10").unwrap(), 2.0);
+        assert!(Calculator::str_2_f("87,5").is_err());
+        assert!(Calculator::str_2_f("1__0").is_err());
+        // An `e` that isn't followed by digits is an unknown
+        assert_eq!(Calculator::parse("2e").unwrap().variables(), ["e"]);
+        assert_eq!(Calculator::parse("2 * e").unwrap().variables(), ["e"]);
+
+        assert_eq!(in_comma("87,5 / 100").unwrap(), 0.875);
+        assert_eq!(in_comma("clamp(87,5; 0; 50)").unwrap(), 50.0);
+        assert_eq!(in_comma("[30..40,5] * 2").unwrap(), 70.5);
+        assert!(in_comma("87.5").is_err());
+        assert!(in_comma("clamp(1, 2, 3)").is_err());
+        assert!(Calculator::str_2_f("clamp(1; 2; 3)").is_err());
+    }
+
     #[test]
     fn test_unary_operators() {
         assert_eq!(Calculator::str_2_f("-5").unwrap(), -5.0);
@@ -393,6 +1222,64 @@ mod tests {
         assert_eq!(Calculator::str_2_f("2(3 + 4)").unwrap(), 14.0);
         assert_eq!(Calculator::str_2_f("5(2 + 3) - 10").unwrap(), 15.0);
         assert_eq!(Calculator::str_2_f("3(4 - 1) + 2").unwrap(), 11.0);
+
+        // Every operand that can end an expression, next to every token that can start one
+        let lefts = ["2", "x", "(2)", "[1..2]", "sqrt(4)", "-2", "2 ^ 2"];
+        let rights = ["3", "y", "(3)", "[1..3]", "sqrt(9)"];
+        let strict = ParseOptions { multiplication: Multiplication::Explicit };
+        for left in lefts {
+            for right in rights {
+                let explicit = Calculator::parse(&format!("{} * {}", left, right)).unwrap();
+                for gap in ["", " "] {
+                    let text = format!("{}{}{}", left, gap, right);
+                    // Would read as one number or name, or as a call of `x`
+                    let joined = gap.is_empty() && text[left.len() - 1..=left.len()].chars().all(char::is_alphanumeric);
+                    if joined || (left, right) == ("x", "(3)") {
+                        continue;
+                    }
+                    assert_eq!(Calculator::parse(&text), Ok(explicit.clone()), "{}", text);
+                    assert_eq!(
+                        Calculator::parse_in(&text, NumberFormat::default(), strict).unwrap_err(),
+                        "Missing operator: write '*' to multiply",
+                        "{}",
+                        text
+                    );
+                }
+            }
+        }
+        // Binds like `*`, so a sign or power on the left goes first and division reads left to right
+        assert_eq!(Calculator::str_2_f("-2(3)").unwrap(), -6.0);
+        assert_eq!(Calculator::str_2_f("2(3)^2").unwrap(), 18.0);
+        assert_eq!(Calculator::str_2_f("6 / 2(3)").unwrap(), 9.0);
+        assert_eq!(Calculator::str_2_f("2 -3").unwrap(), -1.0);
+        assert_eq!(Calculator::parse("x(3)").unwrap_err(), "Unknown function: x");
+        assert!(Calculator::str_2_f("1.2.3").is_err());
+
+        let analysis = Analysis::new_in("2(3) + 4 5", NumberFormat::default(), strict);
+        let spans: Vec<Span> = analysis.errors.iter().map(|(_, span)| span.clone()).collect();
+        assert_eq!(spans, [1..2, 9..10]);
+        assert_eq!(analysis.expr.unwrap().evaluate().unwrap(), 26.0);
+    }
+
+    #[test]
+    fn test_repeated_signs() {
+        assert_eq!(Calculator::str_2_f("2 + + 3").unwrap_err(), "Unexpected token: operator '+'");
+        assert_eq!(Calculator::str_2_f("2 - - 3").unwrap_err(), "Unexpected token: operator '-'");
+        assert_eq!(Calculator::str_2_f("2 * -3").unwrap(), -6.0);
+        assert_eq!(Calculator::str_2_f("2 - (-3)").unwrap(), 5.0);
+        // Spacing doesn't matter, only whether the second sign is the same
+        assert_eq!(Calculator::str_2_f("2 - -3").unwrap_err(), "Unexpected token: operator '-'");
+        assert_eq!(Calculator::str_2_f("2--3").unwrap_err(), "Unexpected token: operator '-'");
+        assert_eq!(Calculator::str_2_f("2 ++3").unwrap_err(), "Unexpected token: operator '+'");
+        assert_eq!(Calculator::str_2_f("2 + -3").unwrap(), -1.0);
+        assert_eq!(Calculator::str_2_f("2 - +3").unwrap(), -1.0);
+        assert_eq!(Calculator::str_2_f("2 + - 3").unwrap(), -1.0);
+
+        // The Marks field underlines the second sign and still reads the rest
+        let analysis = Analysis::new("2 + + 3");
+        assert_eq!(analysis.errors.len(), 1);
+        assert_eq!(analysis.errors[0].1, 4..5);
+        assert_eq!(analysis.expr.unwrap().evaluate().unwrap(), 5.0);
     }
 
     #[test]
@@ -404,14 +1291,98 @@ mod tests {
         assert!(Calculator::str_2_f("").is_err());
     }
 
+    #[test]
+    fn test_spans() {
+        let tokens = Lexer::tokenize("12.5 + 3(4)").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|(_, span)| span.clone()).collect();
+        assert_eq!(spans, [0..4, 5..6, 7..8, 8..9, 9..10, 10..11, 11..11]);
+
+        let analysis = Analysis::new("(25 + 10) / 50");
+        assert!(analysis.errors.is_empty());
+        let (span, expr) = analysis.node_at(2).unwrap();
+        assert_eq!((span.clone(), expr.evaluate().unwrap()), (1..3, 25.0));
+        let (span, expr) = analysis.node_at(4).unwrap();
+        assert_eq!((span.clone(), expr.evaluate().unwrap()), (1..8, 35.0));
+        assert_eq!(analysis.node_at(10).unwrap().0, 0..14);
+        assert_eq!(analysis.matching_paren(0), Some((0..1, 8..9)));
+        assert_eq!(analysis.matching_paren(9), Some((8..9, 0..1)));
+        assert_eq!(analysis.matching_paren(5), None);
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let span = |expr: &str| Analysis::new(expr).errors.first().map(|(_, span)| span.clone());
+        assert_eq!(span("2 $ 3"), Some(2..3));
+        assert_eq!(span("2 + + 3"), Some(4..5));
+        assert_eq!(span("(2 + 3"), Some(0..6));
+        assert_eq!(span("2 +"), Some(3..3));
+        assert_eq!(span("4 / (2 - 2) + 1"), Some(0..11));
+        assert_eq!(span("1.2.3"), Some(0..5));
+        assert_eq!(Analysis::new("2 $ 3").tokens.len(), 2);
+        assert_eq!(Analysis::new("round(x)").tokens[..3], [(TokenKind::Function, 0..5), (TokenKind::Operator('('), 5..6), (TokenKind::Unknown, 6..7)]);
+        assert_eq!(span("(25 + x) / 50"), None);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let analysis = Analysis::new("(2 + * 3) $ + (4");
+        let spans: Vec<Span> = analysis.errors.iter().map(|(_, span)| span.clone()).collect();
+        assert_eq!(spans, [5..6, 10..11, 14..16]);
+        assert_eq!(analysis.expr.unwrap().to_string(), "2 + ? * 3 + 4");
+
+        let analysis = Analysis::new("sqr(4) + clamp(1, 2)");
+        assert_eq!(analysis.errors[0], ("Unknown function: sqr".to_string(), 0..3));
+        assert_eq!(analysis.errors[1].0, "clamp takes 3 arguments, found 2");
+        // The partial expression doesn't evaluate, and strict parsing stops at the first problem in the text
+        assert!(Analysis::new("1 +").expr.unwrap().evaluate().is_err());
+        assert_eq!(Calculator::parse("(2 + * 3) $ + (4").unwrap_err(), "Unexpected token: operator '*'");
+        assert_eq!(Calculator::parse("(2 + 3) $ + (4").unwrap_err(), "Invalid character: '$'");
+        assert_eq!(Calculator::parse(" $ ").unwrap_err(), "Invalid character: '$'");
+        assert_eq!(Calculator::parse("  ").unwrap_err(), "Empty expression");
+
+        // Without problems in the text, the analysis has what strict parsing returns, even if it fails to evaluate
+        for text in ["(25 + x) / 50", "4 / (2 - 2) + x", "2 $ 3", "2 +", "2(3)"] {
+            assert_eq!(Analysis::new(text).parsed(), Calculator::parse(text).ok().as_ref(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let expr = Calculator::parse("(25 + 10 + x) / (50 + 20 + 80) * y + x").unwrap();
+        assert_eq!(expr.variables(), ["x", "y"]);
+        assert_eq!(Calculator::str_2_f("x + 1").unwrap_err(), "Unknown value: x");
+        let value = expr.evaluate_with(&|name| if name == "x" { Some(45.0) } else { Some(1.5) });
+        assert!(close(value.ok(), 80.0 / 150.0 * 1.5 + 45.0));
+        assert!(Calculator::str_2_f("2 $ x").is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let interval = |s: &str| Calculator::parse(s).unwrap().evaluate_interval().unwrap();
+        assert_eq!(interval("[30..40]/50"), Interval { low: 0.6, high: 0.8 });
+        assert_eq!(interval("(25 + [5..10]) / 50"), Interval { low: 0.6, high: 0.7 });
+        assert_eq!(interval("-[1..2] * [3..4]"), Interval { low: -8.0, high: -3.0 });
+        assert_eq!(interval("45/50"), Interval::exact(0.9));
+        assert_eq!(Calculator::str_2_f("[30..40]/50").unwrap(), 0.7);
+        assert_eq!(Calculator::str_2_f("[1.5..2.5]").unwrap(), 2.0);
+        assert!(!Calculator::parse("45/50").unwrap().has_range());
+        assert!(Calculator::parse("10 / [-1..1]").unwrap().evaluate_interval().is_err());
+        assert!(Calculator::str_2_f("[30 40]").is_err());
+        assert!(Calculator::str_2_f("[30..40").is_err());
+        assert!(Calculator::str_2_f("30..40").is_err());
+        let analysis = Analysis::new("([30..40])/50");
+        assert_eq!(analysis.matching_paren(2), Some((1..2, 8..9)));
+        assert_eq!(analysis.matching_paren(10), Some((9..10, 0..1)));
+    }
+
     #[test]
     fn test_weighted_sum() {
-        let grades = vec![85.0, 90.0, 78.0];
-        let weights = vec![0.3, 0.4, 0.3];
+        let grades = [85.0, 90.0, 78.0];
+        let weights = [0.3, 0.4, 0.3];
         assert_eq!(Calculator::weighted_sum(&grades, &weights), Some(84.9));
         
         // Test error cases
-        assert_eq!(Calculator::weighted_sum(&vec![], &vec![]), None);
-        assert_eq!(Calculator::weighted_sum(&vec![1.0], &vec![1.0, 2.0]), None);
+        assert_eq!(Calculator::weighted_sum(&[], &[]), None);
+        assert_eq!(Calculator::weighted_sum(&[1.0], &[1.0, 2.0]), None);
     }
-}
\ No newline at end of file
+}
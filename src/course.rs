@@ -0,0 +1,780 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calculator::{Calculator, ParseOptions};
+use crate::curve::{self, Curve};
+use crate::grade_scale::GradeScale;
+use crate::history::{self, History};
+use crate::number_format::NumberFormat;
+use crate::rules::{self, Rule, RuleOutcome};
+use crate::ui_elements::RowFields;
+
+/// Whether a section counts towards the 100% of regular weight.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SectionKind {
+    #[default]
+    Regular,
+    /// Bonus weight on top of the regular 100%, whose marks may exceed the total
+    ExtraCredit,
+}
+
+/// How a course's final grade is recorded on the transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum GradingBasis {
+    #[default]
+    Graded,
+    PassFail,
+    Audit,
+}
+
+/// A section's grade as a fraction together with its weight in percent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionGrade {
+    pub name: String,
+    pub grade: Option<f32>,
+    pub weight: f32,
+    pub kind: SectionKind,
+    /// Highest allowed grade in percent
+    pub cap: Option<f32>,
+}
+
+impl SectionGrade {
+    pub fn new(name: &str, grade: Option<f32>, weight: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            grade,
+            weight,
+            kind: SectionKind::Regular,
+            cap: None,
+        }
+    }
+}
+
+/// Target of the curves and caps that apply to the final grade rather than one section.
+pub const COURSE_GRADE: &str = "Course grade";
+
+/// A grade in percent before and after a curve or cap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradeChange {
+    pub target: String,
+    pub before: f32,
+    pub after: f32,
+}
+
+/// Weighted sum of every graded section.
+pub fn weighted_grade(sections: &[SectionGrade]) -> Option<f32> {
+    let (grades, weights): (Vec<f32>, Vec<f32>) = sections
+        .iter()
+        .filter_map(|s| s.grade.map(|g| (g, s.weight)))
+        .unzip();
+    Calculator::weighted_sum(&grades, &weights)
+}
+
+/// Hypothetical section grades (as fractions) keyed by row id, laid over the real marks.
+pub type Overlay = BTreeMap<u64, f32>;
+
+/// A named what-if set of marks. The course's real marks are never touched.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    /// Marks expressions keyed by row id; rows without one keep their actual marks
+    pub marks: BTreeMap<u64, String>,
+}
+
+impl Scenario {
+    /// Parses the scenario's marks into an overlay.
+    pub fn overlay(&self, course: &Course) -> Result<Overlay, String> {
+        let mut overlay = Overlay::new();
+        for (id, marks) in self.marks.iter() {
+            if marks.trim().is_empty() {
+                continue;
+            }
+            let grade = Calculator::str_2_f_in(marks, course.number_format, course.parse_options).map_err(|e| {
+                let section = course.row(*id).map(|r| r.section.as_str()).unwrap_or("?");
+                format!("{}: {}", section, e)
+            })?;
+            overlay.insert(*id, grade);
+        }
+        Ok(overlay)
+    }
+}
+
+/// Which grading scheme decides the final grade.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SchemeChoice {
+    /// Whichever scheme gives the highest grade
+    #[default]
+    Best,
+    /// Always the scheme at this index
+    Designated(usize),
+}
+
+/// The grade a single scheme produced, or why it could not be computed.
+#[derive(Debug, Clone)]
+pub struct SchemeGrade {
+    pub name: String,
+    pub grade: Result<f32, String>,
+}
+
+/// Result of evaluating a course under its chosen scheme.
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub weighted: f32,
+    pub grade: f32,
+    pub outcomes: Vec<RuleOutcome>,
+    pub curves: Vec<GradeChange>,
+    pub caps: Vec<GradeChange>,
+    pub warnings: Vec<String>,
+    /// Sections as they went into the weighted sum, after curves and caps
+    pub sections: Vec<SectionGrade>,
+    pub letter: Option<String>,
+    /// Lowest and highest final grade when some marks are ranges
+    pub range: Option<(f32, f32)>,
+    pub scheme: usize,
+    pub schemes: Vec<SchemeGrade>,
+}
+
+// Everything one scheme produces before the best scheme is chosen
+struct SchemeResult {
+    weighted: f32,
+    grade: f32,
+    outcomes: Vec<RuleOutcome>,
+    curves: Vec<GradeChange>,
+    caps: Vec<GradeChange>,
+    warnings: Vec<String>,
+    sections: Vec<SectionGrade>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Course {
+    pub name: String,
+    pub rows: Vec<RowFields>,
+    #[serde(default)]
+    pub rules: String,
+    #[serde(default = "default_schemes")]
+    pub schemes: Vec<String>,
+    #[serde(default)]
+    pub scheme_choice: SchemeChoice,
+    #[serde(default)]
+    pub curves: Vec<Curve>,
+    /// Highest allowed final grade in percent
+    #[serde(default)]
+    pub grade_cap: Option<f32>,
+    #[serde(default = "default_credits")]
+    pub credits: f32,
+    #[serde(default)]
+    pub basis: GradingBasis,
+    #[serde(default)]
+    pub scale: GradeScale,
+    #[serde(default)]
+    pub scenarios: Vec<Scenario>,
+    #[serde(default)]
+    pub number_format: NumberFormat,
+    #[serde(default)]
+    pub parse_options: ParseOptions,
+    /// Kept in its own file next to the course file
+    #[serde(skip)]
+    pub history: History,
+}
+
+fn default_credits() -> f32 {
+    3.0
+}
+
+/// A file kept next to a course file, named after it, e.g. `course.history.json` for `course.json`
+/// and the suffix `history.json`.
+pub fn sidecar_path(course_path: &Path, suffix: &str) -> PathBuf {
+    let stem = course_path.file_stem().and_then(|s| s.to_str()).unwrap_or("course");
+    course_path.with_file_name(format!("{}.{}", stem, suffix))
+}
+
+fn default_schemes() -> Vec<String> {
+    vec!["A".to_string()]
+}
+
+impl Default for Course {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Course {
+    pub fn new() -> Self {
+        let names = ["Assignments", "Project", "Midterm", "Final"];
+        let mut rows = Vec::new();
+        for name in names.iter() {
+            rows.push(RowFields::new_named(name.to_string()));
+        }
+        let mut course = Self {
+            name: String::new(),
+            rows,
+            rules: String::new(),
+            schemes: default_schemes(),
+            scheme_choice: SchemeChoice::Best,
+            curves: Vec::new(),
+            grade_cap: None,
+            credits: default_credits(),
+            basis: GradingBasis::Graded,
+            scale: GradeScale::default(),
+            scenarios: Vec::new(),
+            number_format: NumberFormat::default(),
+            parse_options: ParseOptions::default(),
+            history: History::default(),
+        };
+        course.normalize_rows();
+        course
+    }
+
+    pub fn row(&self, id: u64) -> Option<&RowFields> {
+        self.rows.iter().find(|r| r.id == id)
+    }
+
+    /// Inserts a row at `index`, keeping its id unless it is unset or taken, and returns the id.
+    pub fn insert_row(&mut self, index: usize, row: RowFields) -> u64 {
+        let index = index.min(self.rows.len());
+        self.rows.insert(index, row);
+        self.normalize_rows();
+        self.rows[index].id
+    }
+
+    /// A copy of the row at `index` with a fresh id, ready to be inserted.
+    pub fn duplicate_row(&self, index: usize) -> Option<RowFields> {
+        let mut row = self.rows.get(index)?.clone();
+        row.id = 0;
+        row.section = format!("{} (copy)", row.section);
+        Some(row)
+    }
+
+    /// Adds a weight column, starting from a copy of the first scheme's weights.
+    pub fn add_scheme(&mut self, name: String) {
+        for row in self.rows.iter_mut() {
+            let weight = row.weight(0).to_string();
+            row.weights.push(weight);
+        }
+        self.schemes.push(name);
+        self.normalize_rows();
+    }
+
+    pub fn remove_scheme(&mut self, scheme: usize) {
+        if self.schemes.len() <= 1 || scheme >= self.schemes.len() {
+            return;
+        }
+        self.schemes.remove(scheme);
+        for row in self.rows.iter_mut() {
+            if scheme < row.weights.len() {
+                row.weights.remove(scheme);
+            }
+        }
+        self.scheme_choice = match self.scheme_choice {
+            SchemeChoice::Designated(i) if i == scheme => SchemeChoice::Best,
+            SchemeChoice::Designated(i) if i > scheme => SchemeChoice::Designated(i - 1),
+            choice => choice,
+        };
+    }
+
+    /// Switches the number format, rewriting the numbers in the marks and scenarios to match.
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        let from = self.number_format;
+        for row in self.rows.iter_mut() {
+            row.marks = from.convert(&row.marks, format);
+        }
+        for scenario in self.scenarios.iter_mut() {
+            for marks in scenario.marks.values_mut() {
+                *marks = from.convert(marks, format);
+            }
+        }
+        self.number_format = format;
+        self.normalize_rows();
+        for row in self.rows.iter_mut() {
+            row.update_grade();
+        }
+    }
+
+    /// Switches the grammar the marks are read with and regrades every row. The marks stay as they are.
+    pub fn set_parse_options(&mut self, options: ParseOptions) {
+        self.parse_options = options;
+        self.normalize_rows();
+        for row in self.rows.iter_mut() {
+            row.update_grade();
+        }
+    }
+
+    // Every row carries exactly one weight per scheme, a unique non-zero id and the course's number format and parse options
+    fn normalize_rows(&mut self) {
+        if self.schemes.is_empty() {
+            self.schemes = default_schemes();
+        }
+        let mut next_id = self.rows.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+        let mut seen = Vec::new();
+        for row in self.rows.iter_mut() {
+            row.weights.resize(self.schemes.len(), String::new());
+            row.format = self.number_format;
+            row.parse_options = self.parse_options;
+            if row.id == 0 || seen.contains(&row.id) {
+                row.id = next_id;
+                next_id += 1;
+            }
+            seen.push(row.id);
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut course: Course = serde_json::from_str(&text)?;
+        course.normalize_rows();
+        for row in course.rows.iter_mut() {
+            row.update_grade();
+        }
+        let history_path = history::path_for(path);
+        if history_path.exists() {
+            // A damaged history shouldn't keep the marks from opening
+            course.history = History::load(&history_path).unwrap_or_else(|e| {
+                eprintln!("Could not read {}, starting a new history: {}", history_path.display(), e);
+                History::default()
+            });
+        }
+        Ok(course)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)?;
+        let history_path = history::path_for(path);
+        if !self.history.snapshots.is_empty() {
+            self.history.save(&history_path)?;
+        } else if history_path.exists() {
+            // A cleared history would come back with the next load
+            fs::remove_file(&history_path)?;
+        }
+        Ok(())
+    }
+
+    /// Collects the sections weighted under `scheme`, failing if a graded section has no usable weight.
+    /// Grades in `overlay` take the place of the rows' own.
+    pub fn sections(&self, scheme: usize, overlay: &Overlay) -> Result<Vec<SectionGrade>, String> {
+        let mut sections = Vec::new();
+        for row in self.rows.iter() {
+            let grade = overlay.get(&row.id).copied().or(row.f_section_grade);
+            let (grade, weight) = match grade {
+                Some(grade) => {
+                    let weight = row
+                        .weight(scheme)
+                        .trim()
+                        .parse::<f32>()
+                        .map_err(|_| String::from("Each grade must have a corresponding weight"))?;
+                    (Some(grade), weight)
+                }
+                None => match row.weight(scheme).trim().parse::<f32>() {
+                    Ok(weight) => (None, weight),
+                    Err(_) => continue,
+                },
+            };
+            sections.push(SectionGrade {
+                kind: row.kind,
+                cap: row.cap,
+                ..SectionGrade::new(&row.section, grade, weight)
+            });
+        }
+        Ok(sections)
+    }
+
+    // Section curves and caps, the weighted average under one scheme, the grading rules
+    // and finally the course curves and cap
+    fn evaluate_scheme(&self, rules: &[Rule], scheme: usize, overlay: &Overlay) -> Result<SchemeResult, String> {
+        let mut sections = self.sections(scheme, overlay)?;
+        let warnings = weight_warnings(&sections, self.number_format);
+        let mut curves = curve::curve_sections(&self.curves, &mut sections);
+        let mut caps = cap_sections(&mut sections);
+        let weighted = weighted_grade(&sections)
+            .ok_or_else(|| String::from("Error calculating final grade"))?;
+        let weighed = sections.clone();
+        let (ruled, outcomes) = rules::apply_rules(rules, &mut sections, weighted, self.number_format);
+
+        let curved = curve::curve_course(&self.curves, ruled);
+        if curved != ruled {
+            curves.push(GradeChange { target: COURSE_GRADE.to_string(), before: ruled, after: curved });
+        }
+        let grade = match self.grade_cap {
+            Some(cap) if curved > cap => {
+                caps.push(GradeChange { target: COURSE_GRADE.to_string(), before: curved, after: cap });
+                cap
+            }
+            _ => curved,
+        };
+        Ok(SchemeResult { weighted, grade, outcomes, curves, caps, warnings, sections: weighed })
+    }
+
+    /// Evaluates every scheme and keeps the one selected by `scheme_choice`.
+    pub fn evaluate(&self) -> Result<Evaluation, String> {
+        let mut eval = self.evaluate_with(&Overlay::new())?;
+        eval.range = self.grade_range()?;
+        Ok(eval)
+    }
+
+    /// Final grade with every range in the marks at its low end, and then at its high end.
+    pub fn grade_range(&self) -> Result<Option<(f32, f32)>, String> {
+        let ranged: Vec<_> = self
+            .rows
+            .iter()
+            .filter_map(|r| r.f_section_range.map(|range| (r.id, range)))
+            .collect();
+        if ranged.is_empty() {
+            return Ok(None);
+        }
+        let low: Overlay = ranged.iter().map(|(id, range)| (*id, range.low)).collect();
+        let high: Overlay = ranged.iter().map(|(id, range)| (*id, range.high)).collect();
+        let (low, high) = (self.evaluate_with(&low)?.grade, self.evaluate_with(&high)?.grade);
+        Ok(Some((low.min(high), low.max(high))))
+    }
+
+    /// Like `evaluate`, with some section grades replaced by hypothetical ones.
+    pub fn evaluate_with(&self, overlay: &Overlay) -> Result<Evaluation, String> {
+        let rules = rules::parse_rules(&self.rules).map_err(|e| e.to_string())?;
+        let results: Vec<_> = (0..self.schemes.len())
+            .map(|i| self.evaluate_scheme(&rules, i, overlay))
+            .collect();
+
+        let scheme = match self.scheme_choice {
+            SchemeChoice::Designated(i) if i < results.len() => i,
+            _ => results
+                .iter()
+                .enumerate()
+                .filter_map(|(i, r)| r.as_ref().ok().map(|r| (i, r.grade)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+        };
+        let schemes = self
+            .schemes
+            .iter()
+            .zip(results.iter())
+            .map(|(name, r)| SchemeGrade {
+                name: name.clone(),
+                grade: r.as_ref().map(|r| r.grade).map_err(Clone::clone),
+            })
+            .collect();
+
+        let result = results
+            .into_iter()
+            .nth(scheme)
+            .ok_or_else(|| String::from("Error calculating final grade"))??;
+        Ok(Evaluation {
+            weighted: result.weighted,
+            grade: result.grade,
+            outcomes: result.outcomes,
+            curves: result.curves,
+            caps: result.caps,
+            warnings: result.warnings,
+            sections: result.sections,
+            letter: self.scale.letter(result.grade).map(str::to_string),
+            range: None,
+            scheme,
+            schemes,
+        })
+    }
+}
+
+// Limits every capped section to its cap and reports the ones that were reduced
+fn cap_sections(sections: &mut [SectionGrade]) -> Vec<GradeChange> {
+    let mut caps = Vec::new();
+    for section in sections.iter_mut() {
+        if let (Some(grade), Some(cap)) = (section.grade, section.cap)
+            && grade * 100.0 > cap
+        {
+            caps.push(GradeChange { target: section.name.clone(), before: grade * 100.0, after: cap });
+            section.grade = Some(cap / 100.0);
+        }
+    }
+    caps
+}
+
+// Regular weights should add up to 100, and only extra credit may go past its total
+fn weight_warnings(sections: &[SectionGrade], format: NumberFormat) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let regular: f32 = sections
+        .iter()
+        .filter(|s| s.kind == SectionKind::Regular)
+        .map(|s| s.weight)
+        .sum();
+    if (regular - 100.0).abs() > 0.01 {
+        warnings.push(format!("Regular weights add up to {}, not 100", format.number(regular, 2)));
+    }
+    for s in sections.iter() {
+        if let Some(grade) = s.grade
+            && grade > 1.0
+            && s.kind == SectionKind::Regular
+            && s.cap.is_none()
+        {
+            warnings.push(format!(
+                "{} is {}, above its total. Mark it as extra credit or give it a cap if that is intended",
+                s.name,
+                format.percent(grade * 100.0, 2)
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{close, course};
+
+    #[test]
+    fn test_evaluate_with_rules() {
+        let c = course(
+            &[("Assignments", "45/50", "30"), ("Midterm", "40/100", "30"), ("Final", "60/100", "40")],
+            "replace Midterm with Final if higher\nrequire Final >= 65 else 49",
+        );
+        let eval = c.evaluate().unwrap();
+        assert!(close(eval.weighted, 63.0));
+        assert_eq!(eval.grade, 49.0);
+        assert_eq!(eval.letter.as_deref(), Some("F"));
+        assert!(eval.outcomes.iter().all(|o| o.fired));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let c = course(&[("Assignments", "45/50", "")], "");
+        assert!(c.evaluate().is_err());
+        let c = course(&[("Assignments", "45/50", "30")], "explode");
+        assert!(c.evaluate().is_err());
+        let c = course(&[("Assignments", "", "30")], "");
+        assert!(c.evaluate().is_err());
+    }
+
+    #[test]
+    fn test_schemes() {
+        let mut c = course(
+            &[("Assignments", "27/30", "30"), ("Midterm", "20/40", "30"), ("Final", "85/100", "40")],
+            "",
+        );
+        c.add_scheme("B".to_string());
+        c.rows[1].weights[1] = "0".to_string();
+        c.rows[2].weights[1] = "70".to_string();
+
+        // A: 27 + 15 + 34 = 76, B: 27 + 0 + 59.5 = 86.5
+        let eval = c.evaluate().unwrap();
+        assert_eq!(eval.scheme, 1);
+        assert!(close(eval.grade, 86.5));
+        assert!(close(*eval.schemes[0].grade.as_ref().unwrap(), 76.0));
+
+        c.scheme_choice = SchemeChoice::Designated(0);
+        let eval = c.evaluate().unwrap();
+        assert_eq!(eval.scheme, 0);
+        assert!(close(eval.grade, 76.0));
+
+        c.remove_scheme(0);
+        assert_eq!(c.schemes, vec!["B".to_string()]);
+        assert_eq!(c.scheme_choice, SchemeChoice::Best);
+        assert_eq!(c.rows[2].weights, vec!["70".to_string()]);
+    }
+
+    #[test]
+    fn test_curves() {
+        use crate::curve::{CurveKind, CurveTarget};
+
+        let mut c = course(&[("Midterm", "49/100", "50"), ("Final", "70/100", "50")], "require Final >= 75 else 49");
+        c.curves.push(Curve {
+            target: CurveTarget::Section("Midterm".to_string()),
+            kind: CurveKind::SquareRoot,
+            enabled: true,
+        });
+        c.curves.push(Curve {
+            target: CurveTarget::Section("Final".to_string()),
+            kind: CurveKind::AddPoints(5.0),
+            enabled: true,
+        });
+        c.curves.push(Curve { target: CurveTarget::Course, kind: CurveKind::AddPoints(2.0), enabled: true });
+
+        // 70 * 0.5 + 75 * 0.5 = 72.5, the curved final passes the rule, then +2
+        let eval = c.evaluate().unwrap();
+        assert!(close(eval.weighted, 72.5));
+        assert!(close(eval.grade, 74.5));
+        assert_eq!(eval.curves.len(), 3);
+
+        // Disabling the curves gives back the raw grade
+        c.curves.iter_mut().for_each(|curve| curve.enabled = false);
+        let eval = c.evaluate().unwrap();
+        assert_eq!(eval.grade, 49.0);
+        assert!(eval.curves.is_empty());
+        assert_eq!(c.rows[0].marks, "49/100");
+    }
+
+    #[test]
+    fn test_extra_credit_and_caps() {
+        let mut c = course(
+            &[("Assignments", "33/30", "40"), ("Exam", "50/50", "60"), ("Bonus", "12/10", "5")],
+            "",
+        );
+        let eval = c.evaluate().unwrap();
+        assert_eq!(eval.warnings.len(), 3);
+        assert!(eval.caps.is_empty());
+
+        // 110 * 0.4 + 100 * 0.6 + 120 * 0.05 = 110, the bonus row is not a weight typo
+        c.rows[0].cap = Some(110.0);
+        c.rows[2].kind = SectionKind::ExtraCredit;
+        let eval = c.evaluate().unwrap();
+        assert!(eval.warnings.is_empty());
+        assert!(close(eval.grade, 110.0));
+
+        c.rows[0].cap = Some(100.0);
+        c.grade_cap = Some(100.0);
+        let eval = c.evaluate().unwrap();
+        assert!(close(eval.weighted, 106.0));
+        assert_eq!(eval.grade, 100.0);
+        assert_eq!(eval.caps.len(), 2);
+        assert_eq!(eval.caps[0].target, "Assignments");
+        assert_eq!(eval.caps[1].target, "Course grade");
+    }
+
+    #[test]
+    fn test_scenarios() {
+        let mut c = course(&[("Assignments", "45/50", "40"), ("Final", "", "60")], "");
+        assert!(c.rows[0].id != c.rows[1].id);
+        let final_id = c.rows[1].id;
+
+        let mut low = Scenario { name: "Low".to_string(), ..Default::default() };
+        low.marks.insert(final_id, "70/100".to_string());
+        let mut high = Scenario { name: "High".to_string(), ..Default::default() };
+        high.marks.insert(final_id, "90/100".to_string());
+        c.scenarios = vec![low, high];
+
+        // 36 from the assignments plus 42 or 54 from the final
+        let low = c.evaluate_with(&c.scenarios[0].overlay(&c).unwrap()).unwrap();
+        let high = c.evaluate_with(&c.scenarios[1].overlay(&c).unwrap()).unwrap();
+        assert!(close(low.grade, 78.0));
+        assert!(close(high.grade, 90.0));
+        assert_eq!(high.letter.as_deref(), Some("A-"));
+
+        // The actual marks are untouched
+        assert_eq!(c.rows[1].marks, "");
+        assert!(close(c.evaluate().unwrap().grade, 36.0));
+
+        c.scenarios[0].marks.insert(final_id, "70/".to_string());
+        assert!(c.scenarios[0].overlay(&c).unwrap_err().starts_with("Final"));
+    }
+
+    #[test]
+    fn test_row_ids_survive_save_and_load() {
+        let mut c = course(&[("Assignments", "45/50", "40"), ("Final", "", "60")], "");
+        c.rows[1].id = c.rows[0].id;
+        c.normalize_rows();
+        assert!(c.rows[0].id != c.rows[1].id);
+        let text = serde_json::to_string(&c).unwrap();
+        let loaded: Course = serde_json::from_str(&text).unwrap();
+        assert_eq!(loaded.rows[1].id, c.rows[1].id);
+    }
+
+    #[test]
+    fn test_history_sidecar() {
+        let dir = std::env::temp_dir().join(format!("grade_calculator_history_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("course.json");
+        let mut c = course(&[("Assignments", "45/50", "100")], "");
+        c.history.record(history::Snapshot::of(&c, "Start".to_string(), 1000));
+        c.save(&path).unwrap();
+        assert_eq!(Course::load(&path).unwrap().history.snapshots.len(), 1);
+
+        // Cleared and saved again, it stays cleared
+        c.history.snapshots.clear();
+        c.save(&path).unwrap();
+        assert!(!history::path_for(&path).exists());
+        assert!(Course::load(&path).unwrap().history.snapshots.is_empty());
+
+        // A damaged history still lets the course open
+        fs::write(history::path_for(&path), "{ not json").unwrap();
+        let loaded = Course::load(&path).unwrap();
+        assert!(loaded.history.snapshots.is_empty());
+        assert_eq!(loaded.rows[0].marks, "45/50");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_and_move_keep_references() {
+        let mut c = course(&[("Assignments", "45/50", "40"), ("Final", "", "60")], "");
+        let final_id = c.rows[1].id;
+        let mut scenario = Scenario::default();
+        scenario.marks.insert(final_id, "80/100".to_string());
+
+        let copy = c.duplicate_row(0).unwrap();
+        let copy_id = c.insert_row(1, copy);
+        assert!(copy_id != c.rows[0].id);
+        assert_eq!(c.rows[1].section, "Assignments (copy)");
+        assert_eq!(c.rows[1].f_section_grade, c.rows[0].f_section_grade);
+
+        let row = c.rows.remove(2);
+        c.rows.insert(0, row);
+        let overlay = scenario.overlay(&c).unwrap();
+        assert_eq!(overlay.keys().copied().collect::<Vec<_>>(), [final_id]);
+        assert_eq!(c.row(final_id).unwrap().section, "Final");
+        assert!(c.duplicate_row(5).is_none());
+    }
+
+    #[test]
+    fn test_grade_range() {
+        let c = course(&[("Assignments", "[40..50]/50", "40"), ("Final", "85/100", "60")], "");
+        assert_eq!(c.rows[0].section_grade, "80.00–100.00%");
+        let eval = c.evaluate().unwrap();
+        // The point grade uses the midpoint of the range
+        assert!(close(eval.grade, 87.0));
+        let (low, high) = eval.range.unwrap();
+        assert!(close(low, 83.0));
+        assert!(close(high, 91.0));
+
+        let c = course(&[("Assignments", "45/50", "40"), ("Final", "85/100", "60")], "");
+        assert_eq!(c.evaluate().unwrap().range, None);
+    }
+
+    #[test]
+    fn test_decimal_comma() {
+        let mut c = course(&[("Assignments", "43.5/50", "40"), ("Final", "", "60")], "");
+        let final_id = c.rows[1].id;
+        c.scenarios.push(Scenario { name: "Exam".to_string(), ..Default::default() });
+        c.scenarios[0].marks.insert(final_id, "clamp(87.5, 0, 80) / 100".to_string());
+
+        c.set_number_format(NumberFormat { decimal: crate::number_format::DecimalSeparator::Comma });
+        assert_eq!(c.rows[0].marks, "43,5/50");
+        assert_eq!(c.rows[0].section_grade, "87,00%");
+        assert_eq!(c.scenarios[0].marks[&final_id], "clamp(87,5; 0; 80) / 100");
+        let overlay = c.scenarios[0].overlay(&c).unwrap();
+        assert_eq!(overlay[&final_id], 0.8);
+
+        // Rows added later follow the course's format
+        let mut row = RowFields::new_named("Quiz".to_string());
+        row.marks = "9,5/10".to_string();
+        let index = c.rows.len();
+        c.insert_row(index, row);
+        c.rows[index].update_grade();
+        assert_eq!(c.rows[index].f_section_grade, Some(0.95));
+    }
+
+    #[test]
+    fn test_strict_multiplication() {
+        let mut c = course(&[("Assignments", "2(20)/50", "40"), ("Final", "85/100", "60")], "");
+        assert_eq!(c.rows[0].f_section_grade, Some(0.8));
+
+        let strict = ParseOptions { multiplication: crate::calculator::Multiplication::Explicit };
+        c.set_parse_options(strict);
+        assert_eq!(c.rows[0].marks, "2(20)/50");
+        assert_eq!(c.rows[0].f_section_grade, None);
+        assert_eq!(c.number_format, NumberFormat::default());
+
+        c.set_parse_options(ParseOptions::default());
+        assert_eq!(c.rows[0].f_section_grade, Some(0.8));
+    }
+
+    #[test]
+    fn test_best_scheme_skips_failures() {
+        let mut c = course(&[("Assignments", "27/30", "30"), ("Final", "85/100", "70")], "");
+        c.add_scheme("B".to_string());
+        c.rows[0].weights[1] = String::new();
+        let eval = c.evaluate().unwrap();
+        assert_eq!(eval.scheme, 0);
+        assert!(eval.schemes[1].grade.is_err());
+    }
+}
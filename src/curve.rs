@@ -0,0 +1,183 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::course::{GradeChange, SectionGrade};
+
+/// A transform on a grade expressed as a percentage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CurveKind {
+    /// Adds a fixed number of percentage points
+    AddPoints(f32),
+    /// Scales linearly so that `top` becomes 100
+    LinearRescale { top: f32 },
+    /// 10 * sqrt(grade), so 49 becomes 70 and 100 stays 100
+    SquareRoot,
+    /// Shifts every grade so a class mean of `mean` lands on `target`
+    ShiftToMean { mean: f32, target: f32 },
+}
+
+impl CurveKind {
+    pub fn apply(&self, grade: f32) -> f32 {
+        match self {
+            CurveKind::AddPoints(n) => grade + n,
+            CurveKind::LinearRescale { top } if *top > 0.0 => grade * 100.0 / top,
+            CurveKind::LinearRescale { .. } => grade,
+            CurveKind::SquareRoot => 10.0 * grade.max(0.0).sqrt(),
+            CurveKind::ShiftToMean { mean, target } => grade + (target - mean),
+        }
+    }
+
+    /// Undoes `apply`, giving the raw grade that curves to `curved`.
+    pub fn invert(&self, curved: f32) -> f32 {
+        match self {
+            CurveKind::AddPoints(n) => curved - n,
+            CurveKind::LinearRescale { top } if *top > 0.0 => curved * top / 100.0,
+            CurveKind::LinearRescale { .. } => curved,
+            CurveKind::SquareRoot => (curved.max(0.0) / 10.0).powi(2),
+            CurveKind::ShiftToMean { mean, target } => curved - (target - mean),
+        }
+    }
+}
+
+impl fmt::Display for CurveKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CurveKind::AddPoints(n) => write!(f, "add {} points", n),
+            CurveKind::LinearRescale { top } => write!(f, "rescale so {} becomes 100", top),
+            CurveKind::SquareRoot => write!(f, "square-root curve"),
+            CurveKind::ShiftToMean { mean, target } => write!(f, "shift mean {} to {}", mean, target),
+        }
+    }
+}
+
+/// What a curve applies to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CurveTarget {
+    Section(String),
+    Course,
+}
+
+impl CurveTarget {
+    fn matches_section(&self, name: &str) -> bool {
+        match self {
+            CurveTarget::Section(s) => s.trim().eq_ignore_ascii_case(name.trim()),
+            CurveTarget::Course => false,
+        }
+    }
+}
+
+impl fmt::Display for CurveTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CurveTarget::Section(s) => write!(f, "{}", s),
+            CurveTarget::Course => write!(f, "Course grade"),
+        }
+    }
+}
+
+/// A curve stored with the course. Disabling it restores the raw grade.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Curve {
+    pub target: CurveTarget,
+    pub kind: CurveKind,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn active<'a>(curves: &'a [Curve], pred: impl Fn(&CurveTarget) -> bool + 'a) -> impl Iterator<Item = &'a Curve> + 'a {
+    curves.iter().filter(move |c| c.enabled && pred(&c.target))
+}
+
+/// Applies every enabled section curve to the named section's percentage, in order.
+pub fn curve_section(curves: &[Curve], name: &str, grade: f32) -> f32 {
+    active(curves, |t| t.matches_section(name)).fold(grade, |g, c| c.kind.apply(g))
+}
+
+/// Applies every enabled course curve to the course percentage, in order.
+pub fn curve_course(curves: &[Curve], grade: f32) -> f32 {
+    active(curves, |t| *t == CurveTarget::Course).fold(grade, |g, c| c.kind.apply(g))
+}
+
+/// Reverses `curve_section`, giving the raw percentage for a curved one.
+pub fn uncurve_section(curves: &[Curve], name: &str, curved: f32) -> f32 {
+    let applied: Vec<&Curve> = active(curves, |t| t.matches_section(name)).collect();
+    applied.iter().rev().fold(curved, |g, c| c.kind.invert(g))
+}
+
+/// Curves the section grades in place and reports the ones that changed.
+pub fn curve_sections(curves: &[Curve], sections: &mut [SectionGrade]) -> Vec<GradeChange> {
+    let mut effects = Vec::new();
+    for section in sections.iter_mut() {
+        if let Some(grade) = section.grade {
+            let after = curve_section(curves, &section.name, grade * 100.0) / 100.0;
+            if after != grade {
+                effects.push(GradeChange {
+                    target: section.name.clone(),
+                    before: grade * 100.0,
+                    after: after * 100.0,
+                });
+                section.grade = Some(after);
+            }
+        }
+    }
+    effects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::close;
+
+    #[test]
+    fn test_curve_kinds() {
+        assert!(close(CurveKind::AddPoints(5.0).apply(72.0), 77.0));
+        assert!(close(CurveKind::LinearRescale { top: 88.0 }.apply(66.0), 75.0));
+        assert!(close(CurveKind::SquareRoot.apply(49.0), 70.0));
+        assert!(close(CurveKind::SquareRoot.apply(100.0), 100.0));
+        assert!(close(CurveKind::ShiftToMean { mean: 62.0, target: 70.0 }.apply(55.0), 63.0));
+    }
+
+    #[test]
+    fn test_curves_are_reversible() {
+        let kinds = [
+            CurveKind::AddPoints(3.5),
+            CurveKind::LinearRescale { top: 92.0 },
+            CurveKind::SquareRoot,
+            CurveKind::ShiftToMean { mean: 58.0, target: 65.0 },
+        ];
+        for kind in kinds.iter() {
+            for raw in [0.0, 36.0, 64.0, 81.0, 100.0] {
+                assert!(close(kind.invert(kind.apply(raw)), raw), "{} at {}", kind, raw);
+            }
+        }
+    }
+
+    #[test]
+    fn test_section_and_course_curves() {
+        let curves = vec![
+            Curve { target: CurveTarget::Section("Final".to_string()), kind: CurveKind::SquareRoot, enabled: true },
+            Curve { target: CurveTarget::Section("final".to_string()), kind: CurveKind::AddPoints(2.0), enabled: true },
+            Curve { target: CurveTarget::Section("Midterm".to_string()), kind: CurveKind::AddPoints(50.0), enabled: false },
+            Curve { target: CurveTarget::Course, kind: CurveKind::AddPoints(1.0), enabled: true },
+        ];
+        // sqrt first, then +2
+        assert!(close(curve_section(&curves, "Final", 64.0), 82.0));
+        assert!(close(uncurve_section(&curves, "Final", 82.0), 64.0));
+        assert!(close(curve_section(&curves, "Midterm", 40.0), 40.0));
+        assert!(close(curve_course(&curves, 70.0), 71.0));
+
+        let mut sections = vec![
+            SectionGrade::new("Midterm", Some(0.4), 40.0),
+            SectionGrade::new("Final", Some(0.81), 60.0),
+        ];
+        let effects = curve_sections(&curves, &mut sections);
+        assert_eq!(effects.len(), 1);
+        assert!(close(effects[0].after, 92.0));
+        assert!(close(sections[1].grade.unwrap(), 0.92));
+    }
+}
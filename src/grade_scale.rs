@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+/// A letter grade and the lowest percentage that earns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradeStep {
+    pub letter: String,
+    pub min: f32,
+    pub points: f32,
+}
+
+/// Maps a percentage to a letter grade and grade points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradeScale {
+    pub steps: Vec<GradeStep>,
+}
+
+impl Default for GradeScale {
+    fn default() -> Self {
+        let steps = [
+            ("A+", 97.0, 4.0),
+            ("A", 93.0, 4.0),
+            ("A-", 90.0, 3.7),
+            ("B+", 87.0, 3.3),
+            ("B", 83.0, 3.0),
+            ("B-", 80.0, 2.7),
+            ("C+", 77.0, 2.3),
+            ("C", 73.0, 2.0),
+            ("C-", 70.0, 1.7),
+            ("D+", 67.0, 1.3),
+            ("D", 63.0, 1.0),
+            ("D-", 60.0, 0.7),
+            ("F", 0.0, 0.0),
+        ];
+        Self {
+            steps: steps
+                .iter()
+                .map(|(letter, min, points)| GradeStep { letter: letter.to_string(), min: *min, points: *points })
+                .collect(),
+        }
+    }
+}
+
+impl GradeScale {
+    /// The highest step whose minimum the grade reaches.
+    pub fn step_for(&self, grade: f32) -> Option<&GradeStep> {
+        self.steps
+            .iter()
+            .filter(|s| grade >= s.min)
+            .max_by(|a, b| a.min.total_cmp(&b.min))
+    }
+
+    pub fn letter(&self, grade: f32) -> Option<&str> {
+        self.step_for(grade).map(|s| s.letter.as_str())
+    }
+
+    /// Grade points for a letter, matched case-insensitively.
+    pub fn points_for_letter(&self, letter: &str) -> Option<f32> {
+        self.steps
+            .iter()
+            .find(|s| s.letter.eq_ignore_ascii_case(letter.trim()))
+            .map(|s| s.points)
+    }
+
+    /// A grade passes when it earns any grade points.
+    pub fn passes(&self, grade: f32) -> bool {
+        self.step_for(grade).is_some_and(|s| s.points > 0.0)
+    }
+
+    /// Steps ordered from the highest cutoff down.
+    pub fn sorted(&self) -> Vec<&GradeStep> {
+        let mut steps: Vec<&GradeStep> = self.steps.iter().collect();
+        steps.sort_by(|a, b| b.min.total_cmp(&a.min));
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_scale() {
+        let scale = GradeScale::default();
+        assert_eq!(scale.letter(100.0), Some("A+"));
+        assert_eq!(scale.letter(84.9), Some("B"));
+        assert_eq!(scale.letter(83.0), Some("B"));
+        assert_eq!(scale.letter(59.99), Some("F"));
+        assert_eq!(scale.letter(-5.0), None);
+        assert_eq!(scale.points_for_letter("b+"), Some(3.3));
+        assert_eq!(scale.points_for_letter("P"), None);
+        assert!(scale.passes(60.0));
+        assert!(!scale.passes(12.0));
+    }
+
+    #[test]
+    fn test_unordered_steps() {
+        let scale = GradeScale {
+            steps: vec![
+                GradeStep { letter: "F".to_string(), min: 0.0, points: 0.0 },
+                GradeStep { letter: "A".to_string(), min: 80.0, points: 4.0 },
+                GradeStep { letter: "C".to_string(), min: 50.0, points: 2.0 },
+            ],
+        };
+        assert_eq!(scale.letter(65.0), Some("C"));
+        assert_eq!(scale.letter(80.0), Some("A"));
+        assert_eq!(scale.sorted()[0].letter, "A");
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::course::{self, Course};
+
+// Automatic snapshots closer together than this are merged into one
+const MERGE_SECONDS: u64 = 5 * 60;
+
+/// A row as it was when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotRow {
+    pub id: u64,
+    pub section: String,
+    pub marks: String,
+    pub weights: Vec<String>,
+}
+
+/// The course's marks and final grade at one point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Empty for snapshots taken automatically when marks change
+    pub label: String,
+    /// Final grade in percent as `Course::evaluate` gives it, after rules, curves and caps
+    pub grade: Option<f32>,
+    pub rows: Vec<SnapshotRow>,
+}
+
+/// How a row differs between two snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowChange {
+    Added { section: String, marks: String },
+    Removed { section: String, marks: String },
+    Changed { section: String, before: String, after: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct History {
+    pub snapshots: Vec<Snapshot>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The history file stored next to a course file, e.g. `course.history.json` for `course.json`.
+pub fn path_for(course_path: &Path) -> PathBuf {
+    course::sidecar_path(course_path, "history.json")
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds / 3600, seconds % 3600 / 60)
+}
+
+impl Snapshot {
+    pub fn of(course: &Course, label: String, timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            label,
+            grade: course.evaluate().ok().map(|eval| eval.grade),
+            rows: course
+                .rows
+                .iter()
+                .map(|r| SnapshotRow {
+                    id: r.id,
+                    section: r.section.clone(),
+                    marks: r.marks.clone(),
+                    weights: r.weights.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    // Rules, curves and the scale change the grade without touching a row, so both are compared
+    fn same_standing(&self, other: &Snapshot) -> bool {
+        self.rows == other.rows && self.grade == other.grade
+    }
+}
+
+impl History {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)
+    }
+
+    /// Records a snapshot. Labelled checkpoints are always kept, while automatic ones
+    /// are skipped when neither the rows nor the grade changed. Returns whether anything was recorded.
+    pub fn record(&mut self, snapshot: Snapshot) -> bool {
+        let timestamp = snapshot.timestamp;
+        if !snapshot.label.is_empty() {
+            self.snapshots.push(snapshot);
+            return true;
+        }
+        match self.snapshots.last_mut() {
+            Some(last) if last.same_standing(&snapshot) => false,
+            // Keep typing from filling the history with every keystroke
+            Some(last) if last.label.is_empty() && timestamp.saturating_sub(last.timestamp) < MERGE_SECONDS => {
+                *last = snapshot;
+                true
+            }
+            _ => {
+                self.snapshots.push(snapshot);
+                true
+            }
+        }
+    }
+}
+
+/// Rows added, removed or with different marks or weights between two snapshots.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<RowChange> {
+    let mut changes = Vec::new();
+    for old in before.rows.iter() {
+        match after.rows.iter().find(|r| r.id == old.id) {
+            None => changes.push(RowChange::Removed { section: old.section.clone(), marks: old.marks.clone() }),
+            Some(new) if new.marks != old.marks || new.weights != old.weights => {
+                let describe = |r: &SnapshotRow| format!("{} (weight {})", r.marks, r.weights.join(" / "));
+                changes.push(RowChange::Changed {
+                    section: new.section.clone(),
+                    before: describe(old),
+                    after: describe(new),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for new in after.rows.iter().filter(|r| !before.rows.iter().any(|o| o.id == r.id)) {
+        changes.push(RowChange::Added { section: new.section.clone(), marks: new.marks.clone() });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::course;
+    use crate::ui_elements::RowFields;
+
+    fn set_marks(course: &mut Course, i: usize, marks: &str) {
+        course.rows[i].marks = marks.to_string();
+        course.rows[i].update_grade();
+    }
+
+    fn sample() -> Course {
+        course(&[("Midterm", "", "40"), ("Final", "", "60")], "")
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34");
+        assert_eq!(format_timestamp(1_793_404_800), "2026-10-31 00:00");
+    }
+
+    #[test]
+    fn test_record_merges_and_skips() {
+        let mut course = sample();
+        let mut history = History::default();
+        set_marks(&mut course, 0, "30/50");
+        let auto = |course: &Course, timestamp| Snapshot::of(course, String::new(), timestamp);
+        assert!(history.record(auto(&course, 1000)));
+        assert!(!history.record(auto(&course, 1010)));
+        set_marks(&mut course, 0, "35/50");
+        assert!(history.record(auto(&course, 1060)));
+        assert_eq!(history.snapshots.len(), 1);
+        assert_eq!(history.snapshots[0].grade, Some(28.0));
+
+        assert!(history.record(Snapshot::of(&course, "After midterm".to_string(), 1100)));
+        set_marks(&mut course, 1, "80/100");
+        assert!(history.record(auto(&course, 1200)));
+        assert_eq!(history.snapshots.len(), 3);
+        assert_eq!(history.snapshots[2].grade, Some(76.0));
+
+        // A cap changes the grade without touching a row
+        course.grade_cap = Some(50.0);
+        assert!(history.record(auto(&course, 1220)));
+        assert!(!history.record(auto(&course, 1230)));
+        assert_eq!(history.snapshots.len(), 3);
+        assert_eq!(history.snapshots[2].grade, Some(50.0));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut course = sample();
+        let before = Snapshot::of(&course, String::new(), 0);
+        set_marks(&mut course, 1, "70/100");
+        course.rows.remove(0);
+        course.insert_row(course.rows.len(), RowFields::new_named("Quiz".to_string()));
+        let after = Snapshot::of(&course, String::new(), 1);
+
+        let changes = diff(&before, &after);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0], RowChange::Removed { section: "Midterm".to_string(), marks: String::new() });
+        assert_eq!(
+            changes[1],
+            RowChange::Changed {
+                section: "Final".to_string(),
+                before: " (weight 60)".to_string(),
+                after: "70/100 (weight 60)".to_string(),
+            }
+        );
+        assert!(matches!(&changes[2], RowChange::Added { section, .. } if section == "Quiz"));
+        assert_eq!(path_for(Path::new("dir/course.json")), PathBuf::from("dir/course.history.json"));
+    }
+}
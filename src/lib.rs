@@ -0,0 +1,18 @@
+pub mod ui_elements;
+pub mod calculator;
+pub mod course;
+pub mod rules;
+pub mod curve;
+pub mod grade_scale;
+pub mod semester;
+pub mod transcript;
+pub mod analysis;
+pub mod history;
+pub mod undo;
+pub mod solver;
+pub mod number_format;
+pub mod trace;
+pub mod program;
+
+#[cfg(test)]
+pub(crate) mod test_util;
@@ -1,12 +1,34 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::collections::HashMap;
+
 use eframe::egui;
-use ui_elements::RowHeaders;
+use grade_calculator::analysis::{self, Focus};
+use grade_calculator::{curve, history, rules, semester, trace};
+use grade_calculator::ui_elements::{cell_id, RowFields, RowHeaders};
+use grade_calculator::course::{Course, Evaluation, GradingBasis, Scenario, SchemeChoice};
+use grade_calculator::grade_scale::{GradeScale, GradeStep};
+use grade_calculator::transcript::{RetakePolicy, Term, Transcript, TranscriptCourse};
+use grade_calculator::curve::{Curve, CurveKind, CurveTarget};
+use grade_calculator::history::{RowChange, Snapshot};
+use grade_calculator::undo::{Command, Field, Grading, RowOptions, UndoStack};
+use grade_calculator::calculator::{Multiplication, ParseOptions};
+use grade_calculator::number_format::{DecimalSeparator, NumberFormat};
+
+const RULES_HELP: &str = "One rule per line, percentages out of 100:\n\
+    require <section> >= <min> else <grade>\n\
+    replace <section> with <section> if higher\n\
+    cap <section>, <section> below <threshold>\n\
+Quote section names that contain spaces, # starts a comment";
 
-mod ui_elements;
-mod calculator;
-use crate::ui_elements::RowFields;
-use crate::calculator::Calculator;
+const SHORTCUTS: &[(&str, &str)] = &[
+    ("Tab / Shift+Tab", "Next / previous cell, wrapping to the next row"),
+    ("↑ / ↓", "Same cell in the row above / below"),
+    ("Enter", "Cell below, or a new row from the last cell"),
+    ("Ctrl+Delete", "Delete the focused row"),
+    ("Ctrl+Z / Ctrl+Shift+Z", "Undo / redo"),
+    ("F1", "Show or hide this list"),
+];
 
 fn main() -> eframe::Result {
     let options = eframe::NativeOptions {
@@ -23,31 +45,524 @@ fn main() -> eframe::Result {
     )
 }
 
+fn basis_name(basis: GradingBasis) -> &'static str {
+    match basis {
+        GradingBasis::Graded => "Graded",
+        GradingBasis::PassFail => "Pass/fail",
+        GradingBasis::Audit => "Audit",
+    }
+}
+
+fn decimal_name(decimal: DecimalSeparator) -> &'static str {
+    match decimal {
+        DecimalSeparator::Point => "87.5",
+        DecimalSeparator::Comma => "87,5",
+    }
+}
+
+fn basis_combo(ui: &mut egui::Ui, id: impl std::hash::Hash, basis: &mut GradingBasis) {
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(basis_name(*basis))
+        .show_ui(ui, |ui| {
+            for option in [GradingBasis::Graded, GradingBasis::PassFail, GradingBasis::Audit] {
+                ui.selectable_value(basis, option, basis_name(option));
+            }
+        });
+}
+
+/// Plots final grade against a section score, with a band per letter and the current projection.
+fn draw_grade_chart(ui: &mut egui::Ui, points: &[(f32, f32)], scale: &GradeScale, projection: Option<(f32, f32)>, format: NumberFormat) {
+    let size = egui::vec2(ui.available_width().min(480.0), 220.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect.shrink2(egui::vec2(28.0, 8.0));
+    let top = points.iter().map(|p| p.1).fold(100.0, f32::max);
+    let to_screen = |score: f32, grade: f32| {
+        egui::pos2(
+            egui::remap(score, 0.0..=100.0, rect.left()..=rect.right()),
+            egui::remap(grade.clamp(0.0, top), 0.0..=top, rect.bottom()..=rect.top()),
+        )
+    };
+    let visuals = ui.visuals();
+    let text_color = visuals.weak_text_color();
+    let font = egui::FontId::proportional(10.0);
+
+    // Letter bands, from each cutoff up to the next one
+    let steps = scale.sorted();
+    let mut upper = top;
+    for (i, step) in steps.iter().enumerate() {
+        let band = egui::Rect::from_two_pos(to_screen(0.0, step.min), to_screen(100.0, upper));
+        if i % 2 == 0 {
+            painter.rect_filled(band, 0.0, visuals.faint_bg_color);
+        }
+        painter.line_segment([band.left_bottom(), band.right_bottom()], egui::Stroke::new(0.5, text_color));
+        painter.text(band.right_center() + egui::vec2(4.0, 0.0), egui::Align2::LEFT_CENTER, &step.letter, font.clone(), text_color);
+        upper = step.min;
+    }
+    painter.rect_stroke(rect, 0.0, visuals.widgets.noninteractive.bg_stroke, egui::StrokeKind::Inside);
+    for label in [0.0, 50.0, 100.0] {
+        painter.text(to_screen(0.0, label) - egui::vec2(4.0, 0.0), egui::Align2::RIGHT_CENTER, format.number(label, 0), font.clone(), text_color);
+    }
+
+    let line: Vec<egui::Pos2> = points.iter().map(|&(score, grade)| to_screen(score, grade)).collect();
+    painter.add(egui::Shape::line(line, egui::Stroke::new(2.0, visuals.selection.bg_fill)));
+    if let Some((score, grade)) = projection {
+        painter.circle_filled(to_screen(score, grade), 4.0, visuals.warn_fg_color);
+    }
+
+    if let Some(pos) = response.hover_pos() {
+        let score = egui::remap_clamp(pos.x, rect.left()..=rect.right(), 0.0..=100.0);
+        let nearest = points.iter().min_by(|a, b| (a.0 - score).abs().total_cmp(&(b.0 - score).abs()));
+        if let Some(&(score, grade)) = nearest {
+            painter.circle_stroke(to_screen(score, grade), 3.0, egui::Stroke::new(1.0, visuals.text_color()));
+            let letter = scale.letter(grade).unwrap_or("");
+            response.on_hover_text(format!("{} on the section gives {} ({})", format.percent(score, 0), format.percent(grade, 2), letter));
+        }
+    }
+}
+
+/// Plots each snapshot's grade against its time, labelling checkpoints.
+fn draw_timeline(ui: &mut egui::Ui, snapshots: &[Snapshot], format: NumberFormat) {
+    let size = egui::vec2(ui.available_width().min(480.0), 160.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect.shrink2(egui::vec2(28.0, 8.0));
+    let visuals = ui.visuals();
+    let font = egui::FontId::proportional(10.0);
+    let first = snapshots[0].timestamp as f64;
+    let span = (snapshots[snapshots.len() - 1].timestamp as f64 - first).max(1.0);
+    let top = snapshots.iter().filter_map(|s| s.grade).fold(100.0, f32::max);
+    let to_screen = |timestamp: u64, grade: f32| {
+        let x = if snapshots.len() > 1 { ((timestamp as f64 - first) / span) as f32 } else { 0.5 };
+        egui::pos2(
+            egui::lerp(rect.left()..=rect.right(), x),
+            egui::remap(grade.clamp(0.0, top), 0.0..=top, rect.bottom()..=rect.top()),
+        )
+    };
+
+    painter.rect_stroke(rect, 0.0, visuals.widgets.noninteractive.bg_stroke, egui::StrokeKind::Inside);
+    for label in [0.0, 50.0, 100.0] {
+        painter.text(to_screen(snapshots[0].timestamp, label) - egui::vec2(4.0, 0.0), egui::Align2::RIGHT_CENTER, format.number(label, 0), font.clone(), visuals.weak_text_color());
+    }
+    let points: Vec<(egui::Pos2, &Snapshot)> = snapshots
+        .iter()
+        .filter_map(|s| s.grade.map(|g| (to_screen(s.timestamp, g), s)))
+        .collect();
+    painter.add(egui::Shape::line(points.iter().map(|p| p.0).collect(), egui::Stroke::new(2.0, visuals.selection.bg_fill)));
+    for (pos, snapshot) in points.iter() {
+        painter.circle_filled(*pos, 3.0, visuals.selection.bg_fill);
+        if !snapshot.label.is_empty() {
+            painter.text(*pos - egui::vec2(0.0, 6.0), egui::Align2::CENTER_BOTTOM, &snapshot.label, font.clone(), visuals.text_color());
+        }
+    }
+
+    if let Some(hover) = response.hover_pos() {
+        let nearest = points.iter().min_by(|a, b| (a.0.x - hover.x).abs().total_cmp(&(b.0.x - hover.x).abs()));
+        if let Some((pos, snapshot)) = nearest {
+            painter.circle_stroke(*pos, 5.0, egui::Stroke::new(1.0, visuals.text_color()));
+            let grade = snapshot.grade.unwrap_or_default();
+            response.on_hover_text(format!("{}: {}", history::format_timestamp(snapshot.timestamp), format.percent(grade, 2)));
+        }
+    }
+}
+
+/// Keys that move around the grid while one of its cells has the focus.
+#[derive(Clone, Copy)]
+enum GridKey {
+    Next,
+    Previous,
+    Up,
+    Down,
+    Enter,
+    DeleteRow,
+}
+
+// Consumed so the focused text field doesn't act on them too
+fn grid_key(input: &mut egui::InputState) -> Option<GridKey> {
+    use egui::{Key, Modifiers};
+    let keys = [
+        (Modifiers::SHIFT, Key::Tab, GridKey::Previous),
+        (Modifiers::NONE, Key::Tab, GridKey::Next),
+        (Modifiers::NONE, Key::Enter, GridKey::Enter),
+        (Modifiers::NONE, Key::ArrowUp, GridKey::Up),
+        (Modifiers::NONE, Key::ArrowDown, GridKey::Down),
+        (Modifiers::COMMAND, Key::Delete, GridKey::DeleteRow),
+    ];
+    keys.into_iter()
+        .find(|(modifiers, key, _)| input.consume_key(*modifiers, *key))
+        .map(|(_, _, grid_key)| grid_key)
+}
+
+/// Applies a grid key to the focused cell (row, column) and returns the cell to focus next.
+fn navigate(course: &mut Course, stack: &mut UndoStack, (r, c): (usize, usize), key: GridKey) -> Option<(usize, usize)> {
+    let rows = course.rows.len();
+    let cells = |r: usize| course.rows[r].cells();
+    match key {
+        GridKey::Next if c + 1 < cells(r) => Some((r, c + 1)),
+        GridKey::Next if r + 1 < rows => Some((r + 1, 0)),
+        GridKey::Previous if c > 0 => Some((r, c - 1)),
+        GridKey::Previous if r > 0 => Some((r - 1, cells(r - 1) - 1)),
+        GridKey::Up if r > 0 => Some((r - 1, c)),
+        GridKey::Down if r + 1 < rows => Some((r + 1, c)),
+        GridKey::Enter if r + 1 < rows => Some((r + 1, c)),
+        GridKey::Enter if c + 1 == cells(r) => {
+            stack.execute(Command::InsertRow { index: rows, row: RowFields::new() }, course);
+            Some((rows, 0))
+        }
+        GridKey::DeleteRow => {
+            let row = course.rows[r].clone();
+            stack.execute(Command::RemoveRow { index: r, row }, course);
+            record_snapshot(course);
+            (rows > 1).then(|| (r.min(rows - 2), c))
+        }
+        // nowhere to go, keep the focus where it is
+        _ => Some((r, c)),
+    }
+}
+
+/// Payload of a row being dragged by its handle: the row's index.
+struct RowDrag(usize);
+
+/// What the row menu or a drag asked for, applied once the grid is drawn.
+enum RowAction {
+    Move(usize, usize),
+    Insert(usize),
+    Duplicate(usize),
+    Remove(usize),
+}
+
+fn row_menu(ui: &mut egui::Ui, i: usize, rows: usize, action: &mut Option<RowAction>) {
+    let mut item = |ui: &mut egui::Ui, enabled: bool, text: &str, chosen: RowAction| {
+        if ui.add_enabled(enabled, egui::Button::new(text)).clicked() {
+            *action = Some(chosen);
+            ui.close_menu();
+        }
+    };
+    item(ui, true, "Insert above", RowAction::Insert(i));
+    item(ui, true, "Insert below", RowAction::Insert(i + 1));
+    item(ui, true, "Duplicate", RowAction::Duplicate(i));
+    ui.separator();
+    item(ui, i > 0, "Move up", RowAction::Move(i, i.saturating_sub(1)));
+    item(ui, i + 1 < rows, "Move down", RowAction::Move(i, i + 1));
+    ui.separator();
+    item(ui, true, "Delete", RowAction::Remove(i));
+}
+
+/// While a row is dragged over the grid, the slot it would drop into (0 is above the first row).
+fn drop_slot(ui: &egui::Ui, handles: &[egui::Rect]) -> Option<usize> {
+    if !egui::DragAndDrop::has_payload_of_type::<RowDrag>(ui.ctx()) {
+        return None;
+    }
+    let pointer = ui.ctx().pointer_interact_pos()?;
+    if !ui.min_rect().expand(12.0).contains(pointer) {
+        return None;
+    }
+    Some(handles.iter().filter(|r| r.center().y < pointer.y).count())
+}
+
+/// Undo commands for the text fields and menu options of a row that changed while it was drawn.
+fn field_edits(row: &RowFields, (section, marks, weights, options): (String, String, Vec<String>, RowOptions)) -> Vec<Command> {
+    let edit = |field, before: String, after: &String| Command::EditField { row_id: row.id, field, before, after: after.clone() };
+    let mut edits = Vec::new();
+    if section != row.section {
+        edits.push(edit(Field::Section, section, &row.section));
+    }
+    if marks != row.marks {
+        edits.push(edit(Field::Marks, marks, &row.marks));
+    }
+    for (i, (before, after)) in weights.into_iter().zip(row.weights.iter()).enumerate() {
+        if before != *after {
+            edits.push(edit(Field::Weight(i), before, after));
+        }
+    }
+    if options != RowOptions::of(row) {
+        edits.push(Command::SetRowOptions { row_id: row.id, before: options, after: RowOptions::of(row) });
+    }
+    edits
+}
+
+/// Records what a panel below the grid changed in the grading as one undoable edit, with a snapshot of the new grade.
+fn record_grading(course: &mut Course, stack: &mut UndoStack, before: Grading) {
+    let after = Grading::of(course);
+    if after != before {
+        stack.record(Command::SetGrading { before: Box::new(before), after: Box::new(after) });
+        record_snapshot(course);
+    }
+}
+
+/// Applies `change` to a copy of the course and executes it as one undoable replacement, with a snapshot of the new grade.
+/// Used for edits that rewrite or regrade every row at once, such as the number format or a scheme.
+fn replace_course(course: &mut Course, stack: &mut UndoStack, change: impl FnOnce(&mut Course)) {
+    let mut after = course.clone();
+    change(&mut after);
+    stack.execute(Command::ReplaceCourse { before: Box::new(course.clone()), after: Box::new(after) }, course);
+    record_snapshot(course);
+}
+
+fn segment_color(i: usize, count: usize) -> egui::Color32 {
+    egui::ecolor::Hsva::new(i as f32 / count as f32, 0.55, 0.75, 1.0).into()
+}
+
+/// Draws the breakdown as a stacked bar and returns the row of a clicked segment.
+fn draw_breakdown(ui: &mut egui::Ui, parts: &[analysis::Contribution], selected: Option<u64>, format: NumberFormat) -> Option<u64> {
+    let width = ui.available_width().min(480.0);
+    let total: f32 = parts.iter().map(|p| p.earned + p.lost + p.ungraded).sum::<f32>().max(100.0);
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(width, 28.0), egui::Sense::hover());
+    let visuals = ui.visuals().clone();
+    let mut clicked = None;
+    let mut x = rect.left();
+    for (i, part) in parts.iter().enumerate() {
+        let color = segment_color(i, parts.len());
+        let segments = [
+            (part.earned, color, "earned"),
+            (part.lost, color.gamma_multiply(0.3), "lost"),
+            (part.ungraded, visuals.faint_bg_color, "ungraded"),
+        ];
+        for (points, fill, what) in segments {
+            if points <= 0.0 {
+                continue;
+            }
+            let w = points / total * rect.width();
+            let segment = egui::Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2(w, rect.height()));
+            x += w;
+            let response = ui
+                .interact(segment, ui.id().with(("breakdown", part.row_id, what)), egui::Sense::click())
+                .on_hover_text(format!("{}: {} points {}", part.section, format.number(points, 2), what));
+            ui.painter().rect_filled(segment, 0.0, fill);
+            if selected == Some(part.row_id) || response.hovered() {
+                ui.painter().rect_stroke(segment, 0.0, egui::Stroke::new(2.0, visuals.strong_text_color()), egui::StrokeKind::Inside);
+            }
+            if response.clicked() {
+                clicked = Some(part.row_id);
+            }
+        }
+    }
+    ui.painter().rect_stroke(rect, 0.0, visuals.widgets.noninteractive.bg_stroke, egui::StrokeKind::Inside);
+
+    ui.horizontal_wrapped(|ui| {
+        for (i, part) in parts.iter().enumerate() {
+            let text = egui::RichText::new(format!("■ {}", part.section)).color(segment_color(i, parts.len()));
+            if ui.selectable_label(selected == Some(part.row_id), text).clicked() {
+                clicked = Some(part.row_id);
+            }
+        }
+    });
+    clicked
+}
+
+/// Takes an automatic history snapshot, after anything that can change the grade.
+fn record_snapshot(course: &mut Course) {
+    let snapshot = Snapshot::of(course, String::new(), history::now());
+    course.history.record(snapshot);
+}
+
+/// What the central panel shows.
+#[derive(Debug, PartialEq)]
+enum View {
+    Course,
+    Semester,
+    Transcript,
+}
+
+/// Results worked out from the active course as it was when they were asked for, kept until
+/// the course changes or Calculate is clicked so the panels don't re-evaluate it every frame.
+#[derive(Default)]
+struct AnalysisCache {
+    course: Option<Course>,
+    average: Option<Option<f32>>,
+    focus: Option<Vec<Focus>>,
+    charts: HashMap<u64, GradeChart>,
+    breakdown: Option<Vec<analysis::Contribution>>,
+    explanation: Option<Result<Vec<trace::TraceStep>, String>>,
+}
+
+/// The grade chart of one unfinished section.
+struct GradeChart {
+    points: Vec<(f32, f32)>,
+    /// (current average %, final grade %) when the section scores the average
+    projection: Option<(f32, f32)>,
+}
+
+impl AnalysisCache {
+    // Forgets everything when `course` isn't the one the results came from
+    fn check(&mut self, course: &Course) {
+        if self.course.as_ref() != Some(course) {
+            *self = AnalysisCache { course: Some(course.clone()), ..Default::default() };
+        }
+    }
+
+    fn focus(&mut self, course: &Course) -> &[Focus] {
+        self.check(course);
+        self.focus.get_or_insert_with(|| analysis::focus_list(course))
+    }
+
+    fn average(&mut self, course: &Course) -> Option<f32> {
+        self.check(course);
+        *self.average.get_or_insert_with(|| analysis::current_average(course))
+    }
+
+    fn breakdown(&mut self, course: &Course) -> &[analysis::Contribution] {
+        self.check(course);
+        self.breakdown.get_or_insert_with(|| analysis::breakdown(course))
+    }
+
+    fn explanation(&mut self, course: &Course) -> &Result<Vec<trace::TraceStep>, String> {
+        self.check(course);
+        self.explanation.get_or_insert_with(|| trace::explain(course))
+    }
+
+    fn chart(&mut self, course: &Course, row_id: u64) -> &GradeChart {
+        let average = self.average(course);
+        self.charts.entry(row_id).or_insert_with(|| GradeChart {
+            points: analysis::grade_curve(course, average.unwrap_or(0.0), row_id, 101),
+            projection: average.and_then(|a| analysis::projected_grade(course, a, row_id, a).map(|g| (a * 100.0, g))),
+        })
+    }
+}
+
+/// Where the sidebar can take the central panel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Destination {
+    Course(usize),
+    Semester,
+    Transcript,
+}
+
 struct MyApp {
     row_header: RowHeaders,
-    rows: Vec<RowFields>,
-    _course_name: String,
-    _final_grade: Option<f32>,
-    _final_message: String
+    courses: Vec<Course>,
+    active: usize,
+    view: View,
+    transcript: Transcript,
+    transcript_path: String,
+    term_name: String,
+    target_gpa: f32,
+    next_credits: f32,
+    file_path: String,
+    evaluation: Option<Evaluation>,
+    scenario_results: Vec<(String, Result<Evaluation, String>)>,
+    final_message: String,
+    curve_goal: f32,
+    chart_row: Option<u64>,
+    selected_row: Option<u64>,
+    focus_selected: bool,
+    checkpoint_label: String,
+    diff_pair: (usize, usize),
+    /// One undo stack per course
+    undo: Vec<UndoStack>,
+    last_focus: Option<egui::Id>,
+    show_shortcuts: bool,
+    analysis: AnalysisCache
 }
 
 impl MyApp {
     fn new() -> Self {
-        let names = ["Assignments", "Project", "Midterm", "Final"];
-        let mut rows = Vec::new();
-        for name in names.iter() {
-            rows.push(RowFields::new_named(name.to_string()));
-        }
         Self {
             row_header: RowHeaders::new(),
-            rows,
-            _course_name: "".to_string(),
-            _final_grade: None,
-            _final_message: String::new()
+            courses: vec![Course::new()],
+            active: 0,
+            view: View::Course,
+            transcript: Transcript::new(),
+            transcript_path: "transcript.json".to_string(),
+            term_name: String::new(),
+            target_gpa: 3.0,
+            next_credits: 15.0,
+            file_path: "course.json".to_string(),
+            evaluation: None,
+            scenario_results: Vec::new(),
+            final_message: String::new(),
+            curve_goal: 50.0,
+            chart_row: None,
+            selected_row: None,
+            focus_selected: false,
+            checkpoint_label: String::new(),
+            diff_pair: (0, 0),
+            undo: vec![UndoStack::default()],
+            last_focus: None,
+            show_shortcuts: false,
+            analysis: AnalysisCache::default()
+        }
+    }
+
+    fn calculate(&mut self) {
+        let course = &self.courses[self.active];
+        self.analysis = AnalysisCache::default();
+        self.scenario_results = course
+            .scenarios
+            .iter()
+            .map(|scenario| {
+                let result = scenario.overlay(course).and_then(|overlay| course.evaluate_with(&overlay));
+                (scenario.name.clone(), result)
+            })
+            .collect();
+        match course.evaluate() {
+            Ok(eval) => {
+                let letter = eval.letter.as_ref().map(|l| format!(" ({})", l)).unwrap_or_default();
+                let grade = course.number_format.percent(eval.grade, 2);
+                self.final_message = if eval.schemes.len() > 1 {
+                    format!("Final grade is {}{}, scheme {}", grade, letter, eval.schemes[eval.scheme].name)
+                } else {
+                    format!("Final grade is {}{}", grade, letter)
+                };
+                self.evaluation = Some(eval);
+            }
+            Err(e) => {
+                self.final_message = e;
+                self.evaluation = None;
+            }
         }
     }
 
+    /// F1, Ctrl+Z and Ctrl+Shift+Z, taken before any text field can use them for its own undo.
+    /// Typing is merged into one step until the focus moves to another widget.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::F1)) {
+            self.show_shortcuts = !self.show_shortcuts;
+        }
+        let course = &mut self.courses[self.active];
+        let stack = &mut self.undo[self.active];
+        let focus = ctx.memory(|m| m.focused());
+        if focus != self.last_focus {
+            stack.end_edit();
+            self.last_focus = focus;
+        }
+        if self.view != View::Course {
+            return;
+        }
+        let redo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+        let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+        let changed = if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+            stack.redo(course)
+        } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+            stack.undo(course)
+        } else {
+            false
+        };
+        if changed {
+            record_snapshot(course);
+        }
+    }
+
+    fn render_shortcuts(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_shortcuts;
+        egui::Window::new("Keyboard shortcuts")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                egui::Grid::new("shortcuts").striped(true).show(ui, |ui| {
+                    for (keys, action) in SHORTCUTS {
+                        ui.strong(*keys);
+                        ui.label(*action);
+                        ui.end_row();
+                    }
+                });
+            });
+        self.show_shortcuts = open && !ctx.input(|i| i.key_pressed(egui::Key::Escape));
+    }
+
     fn render_header(&mut self, ctx: &egui::Context) {
+        let course = &mut self.courses[self.active];
+        let stack = &mut self.undo[self.active];
         egui::TopBottomPanel::top("header")
             .resizable(false)
             .show(ctx, |ui| {
@@ -55,21 +570,88 @@ impl MyApp {
                 ui.end_row();
                 ui.horizontal(|ui| {
                     ui.add_space(16.0);
-                    ui.add_sized([ui.available_width() / 3.0, 20.0], egui::TextEdit::singleline(&mut self._course_name));
+                    ui.add_sized([ui.available_width() / 3.0, 20.0], egui::TextEdit::singleline(&mut course.name));
                     ui.add_space(8.0);
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut self.file_path);
+                    if ui.button("Save").clicked() {
+                        self.final_message = match course.save(self.file_path.as_ref()) {
+                            Ok(()) => format!("Saved to {}", self.file_path),
+                            Err(e) => format!("Could not save {}: {}", self.file_path, e),
+                        };
+                    }
+                    if ui.button("Open").clicked() {
+                        match Course::load(self.file_path.as_ref()) {
+                            Ok(mut loaded) => {
+                                // Replacing keeps the history, which goes with the file instead
+                                let history = std::mem::take(&mut loaded.history);
+                                let before = Box::new(course.clone());
+                                stack.execute(Command::ReplaceCourse { before, after: Box::new(loaded) }, course);
+                                course.history = history;
+                                self.evaluation = None;
+                                self.scenario_results.clear();
+                                self.final_message = format!("Opened {}", self.file_path);
+                            }
+                            Err(e) => self.final_message = format!("Could not open {}: {}", self.file_path, e),
+                        }
+                    }
+                    ui.add_space(8.0);
+                    if ui.add_enabled(stack.can_undo(), egui::Button::new("Undo")).on_hover_text("Ctrl+Z").clicked()
+                        && stack.undo(course)
+                    {
+                        record_snapshot(course);
+                    }
+                    if ui.add_enabled(stack.can_redo(), egui::Button::new("Redo")).on_hover_text("Ctrl+Shift+Z").clicked()
+                        && stack.redo(course)
+                    {
+                        record_snapshot(course);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add_space(16.0);
+                    ui.label("Credits:");
+                    ui.add(egui::DragValue::new(&mut course.credits).speed(0.5).range(0.0..=30.0));
+                    ui.label("Basis:");
+                    basis_combo(ui, "grading_basis", &mut course.basis);
+                    ui.label("Decimal:");
+                    let mut decimal = course.number_format.decimal;
+                    egui::ComboBox::from_id_salt("decimal_separator")
+                        .selected_text(decimal_name(decimal))
+                        .show_ui(ui, |ui| {
+                            for option in [DecimalSeparator::Point, DecimalSeparator::Comma] {
+                                ui.selectable_value(&mut decimal, option, decimal_name(option));
+                            }
+                        })
+                        .response
+                        .on_hover_text("How numbers are written in the marks and shown as grades");
+                    let format = NumberFormat { decimal };
+                    if format != course.number_format {
+                        replace_course(course, stack, |after| after.set_number_format(format));
+                    }
+                    let mut implicit = course.parse_options.multiplication == Multiplication::Implicit;
+                    ui.checkbox(&mut implicit, "2(3) multiplies")
+                        .on_hover_text("Whether operands side by side, as in 2(3 + 4) or 2x, multiply. Untick to require a *");
+                    let multiplication = if implicit { Multiplication::Implicit } else { Multiplication::Explicit };
+                    if multiplication != course.parse_options.multiplication {
+                        replace_course(course, stack, |after| after.set_parse_options(ParseOptions { multiplication }));
+                    }
                 });
             });
         }
 
     fn render_grid(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
+            let course = &mut self.courses[self.active];
+            let stack = &mut self.undo[self.active];
             // compute column widths
             let total_width = ui.available_width() - 16.0;
             let spacing_x  = 8.0;
-            let delete_w   = 24.0;              
-            // subtract out the 4 gaps between columns:
-            let total_gaps = spacing_x * 3.0;   
-            let field_w    = (total_width - delete_w - total_gaps) / 3.0;
+            // drag handle and delete button
+            let delete_w   = 52.0;
+            // section, marks and grade plus one weight column per scheme
+            let columns    = 3.0 + course.schemes.len() as f32;
+            let total_gaps = spacing_x * columns;
+            let field_w    = (total_width - delete_w - total_gaps) / columns;
 
             egui::Grid::new("grade_grid")
                 .spacing([spacing_x, 4.0])
@@ -77,56 +659,894 @@ impl MyApp {
                 .max_col_width(field_w)
                 .show(ui, |ui| {
                     // ——— Headers ———
-                    self.row_header.render(ui);
+                    self.row_header.render(ui, &course.schemes);
                     ui.end_row();
 
                     // ——— Rows ———
-                    let mut remove_idx = None;
-                    for (i, row) in self.rows.iter_mut().enumerate() {
-                        // delete button (fixed width)
-                        if ui
-                            .add_sized([delete_w, 20.0], egui::Button::new("❌"))
-                            .clicked()
-                        {
-                            remove_idx = Some(i);
-                        }
+                    let focus = ui.memory(|m| m.focused());
+                    let focused_cell = course.rows.iter().enumerate().find_map(|(r, row)| {
+                        (0..row.cells()).find(|c| Some(cell_id(row.id, *c)) == focus).map(|c| (r, c))
+                    });
+                    let key = focused_cell.and_then(|_| ui.input_mut(grid_key));
+
+                    let mut action = None;
+                    let rows = course.rows.len();
+                    let mut handles = Vec::new();
+                    let mut grade_changed = false;
+                    let mut edits = Vec::new();
+                    for (i, row) in course.rows.iter_mut().enumerate() {
+                        // drag handle and delete button (fixed width)
+                        ui.horizontal(|ui| {
+                            let handle = ui
+                                .dnd_drag_source(egui::Id::new(("row_handle", row.id)), RowDrag(i), |ui| {
+                                    ui.add_sized([20.0, 20.0], egui::Label::new("☰").selectable(false));
+                                })
+                                .response
+                                .on_hover_text("Drag to reorder, right-click for more");
+                            handle.context_menu(|ui| row_menu(ui, i, rows, &mut action));
+                            handles.push(handle.rect);
+                            let delete = ui
+                                .add_sized([24.0, 20.0], egui::Button::new("❌"))
+                                .on_hover_text("Right-click for more");
+                            if delete.clicked() {
+                                action = Some(RowAction::Remove(i));
+                            }
+                            delete.context_menu(|ui| row_menu(ui, i, rows, &mut action));
+                        });
 
                         // text inputs (equal width)
-                        let marks_changed = row.render(ui);
+                        let before = (row.section.clone(), row.marks.clone(), row.weights.clone(), RowOptions::of(row));
+                        let selected = self.selected_row == Some(row.id);
+                        let marks_changed = row.render(ui, selected, selected && self.focus_selected);
+                        edits.extend(field_edits(row, before));
                         if marks_changed {
-                            calculator::print_parsed(&row.marks);
-                            let temp = Calculator::str_2_f(&row.marks);
-                            if let Ok(grade) = temp {
-                                row.f_section_grade = Some(grade);
-                                row.section_grade = format!("{:.2}%", grade);
-                            } else {
-                                row.f_section_grade = None;
-                                row.section_grade = "Error".to_string();
-                            }
+                            row.update_grade();
+                            grade_changed = true;
                         }
 
                         ui.end_row();
                     }
-                    if let Some(i) = remove_idx {
-                        self.rows.remove(i);
+                    for edit in edits {
+                        grade_changed |= matches!(edit, Command::EditField { field: Field::Weight(_), .. } | Command::SetRowOptions { .. });
+                        stack.record(edit);
                     }
+                    if let Some(slot) = drop_slot(ui, &handles) {
+                        let slot_y = match handles.get(slot) {
+                            Some(rect) => rect.top() - 2.0,
+                            None => handles.last().map_or(0.0, |r| r.bottom() + 2.0),
+                        };
+                        let x = ui.min_rect().x_range();
+                        ui.painter().hline(x, slot_y, egui::Stroke::new(2.0, ui.visuals().selection.bg_fill));
+                        if ui.input(|i| i.pointer.any_released())
+                            && let Some(drag) = egui::DragAndDrop::take_payload::<RowDrag>(ui.ctx())
+                        {
+                            // the slot counts the dragged row itself when moving down
+                            let to = if slot > drag.0 { slot - 1 } else { slot };
+                            if to != drag.0 {
+                                action = Some(RowAction::Move(drag.0, to));
+                            }
+                        }
+                    }
+                    match action {
+                        Some(RowAction::Move(from, to)) => stack.execute(Command::MoveRow { from, to }, course),
+                        Some(RowAction::Insert(index)) => {
+                            stack.execute(Command::InsertRow { index, row: RowFields::new() }, course)
+                        }
+                        Some(RowAction::Duplicate(i)) => {
+                            if let Some(row) = course.duplicate_row(i) {
+                                stack.execute(Command::InsertRow { index: i + 1, row }, course);
+                                grade_changed = true;
+                            }
+                        }
+                        Some(RowAction::Remove(i)) => {
+                            let row = course.rows[i].clone();
+                            stack.execute(Command::RemoveRow { index: i, row }, course);
+                            grade_changed = true;
+                        }
+                        None => {}
+                    }
+                    if let (Some(cell), Some(key), None) = (focused_cell, key, &action)
+                        && let Some((r, c)) = navigate(course, stack, cell, key)
+                    {
+                        let row = &course.rows[r];
+                        ui.memory_mut(|m| m.request_focus(cell_id(row.id, c.min(row.cells() - 1))));
+                    }
+                    if grade_changed {
+                        record_snapshot(course);
+                    }
+                    self.focus_selected = false;
                 }
             );
+
+            ui.add_space(8.0);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                self.render_schemes(ui);
+                self.render_curves(ui);
+                self.render_rules(ui);
+                self.render_scenarios(ui);
+                self.render_focus(ui);
+                self.render_chart(ui);
+                self.render_breakdown(ui);
+                self.render_explanation(ui);
+                self.render_history(ui);
+                self.render_grade_scale(ui);
+            });
+        });
+    }
+
+    fn render_rules(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        let before = Grading::of(course);
+        ui.collapsing("Grading rules", |ui| {
+            ui.add(
+                egui::TextEdit::multiline(&mut course.rules)
+                    .hint_text("require Final >= 50 else 49\nreplace Midterm with Final if higher\ncap Midterm, Final below 50")
+                    .desired_rows(3)
+                    .desired_width(f32::INFINITY),
+            )
+            .on_hover_text(RULES_HELP);
+            if let Err(e) = rules::parse_rules(&course.rules) {
+                ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+            }
+            ui.horizontal(|ui| {
+                let mut capped = course.grade_cap.is_some();
+                if ui.checkbox(&mut capped, "Cap final grade at").changed() {
+                    course.grade_cap = if capped { Some(100.0) } else { None };
+                }
+                if let Some(cap) = course.grade_cap.as_mut() {
+                    ui.add(egui::DragValue::new(cap).speed(0.5).suffix("%"));
+                }
+            });
+        });
+        record_grading(course, &mut self.undo[self.active], before);
+    }
+
+    fn render_scenarios(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        let before = course.scenarios.clone();
+        ui.collapsing("Scenarios", |ui| {
+            ui.label("Try hypothetical marks without changing your real ones. Leave a cell empty to keep the actual marks.");
+            let mut remove = None;
+            egui::Grid::new("scenario_grid").striped(true).show(ui, |ui| {
+                ui.strong("Section");
+                ui.strong("Actual");
+                for (i, scenario) in course.scenarios.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut scenario.name).desired_width(80.0));
+                        if ui.small_button("❌").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                ui.end_row();
+
+                for row in course.rows.iter() {
+                    ui.label(&row.section);
+                    ui.label(&row.marks);
+                    for scenario in course.scenarios.iter_mut() {
+                        let mut marks = scenario.marks.get(&row.id).cloned().unwrap_or_default();
+                        if ui.add(egui::TextEdit::singleline(&mut marks).desired_width(100.0)).changed() {
+                            if marks.is_empty() {
+                                scenario.marks.remove(&row.id);
+                            } else {
+                                scenario.marks.insert(row.id, marks);
+                            }
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                course.scenarios.remove(i);
+            }
+            if ui.button("Add scenario").clicked() {
+                course.scenarios.push(Scenario {
+                    name: format!("Scenario {}", course.scenarios.len() + 1),
+                    ..Default::default()
+                });
+            }
         });
+        if course.scenarios != before {
+            let after = course.scenarios.clone();
+            self.undo[self.active].record(Command::SetScenarios { before, after });
+        }
+    }
+
+    fn render_focus(&mut self, ui: &mut egui::Ui) {
+        let course = &self.courses[self.active];
+        let cache = &mut self.analysis;
+        let fmt = course.number_format;
+        ui.collapsing("Where to focus", |ui| {
+            ui.label("Unfinished sections ranked by final-grade points per percentage point. Other unfinished sections are assumed to match your current average.");
+            let list = cache.focus(course);
+            if list.is_empty() {
+                ui.label("No unfinished sections");
+                return;
+            }
+            egui::Grid::new("focus_grid").striped(true).show(ui, |ui| {
+                ui.strong("Section");
+                ui.strong("Points per %");
+                ui.strong("Needed for");
+                ui.end_row();
+                for focus in list.iter() {
+                    ui.label(&focus.section);
+                    ui.label(fmt.number(focus.impact, 3));
+                    let needed: Vec<String> = focus
+                        .targets
+                        .iter()
+                        .filter_map(|t| match t.needed {
+                            analysis::Needed::Score(score) => Some(format!("{} {}", t.letter, fmt.percent(score, 1))),
+                            _ => None,
+                        })
+                        .collect();
+                    ui.label(if needed.is_empty() { "Nothing left to change".to_string() } else { needed.join(", ") })
+                        .on_hover_text(focus.describe(fmt));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    fn render_chart(&mut self, ui: &mut egui::Ui) {
+        let course = &self.courses[self.active];
+        let chart_row = &mut self.chart_row;
+        let cache = &mut self.analysis;
+        ui.collapsing("Grade chart", |ui| {
+            let unfinished = analysis::unfinished(course);
+            if unfinished.is_empty() {
+                ui.label("No unfinished sections");
+                return;
+            }
+            let id = match *chart_row {
+                Some(id) if unfinished.contains(&id) => id,
+                _ => unfinished[0],
+            };
+            let name = |id: u64| course.row(id).map(|r| r.section.clone()).unwrap_or_default();
+            ui.horizontal(|ui| {
+                ui.label("Final grade against the score on");
+                egui::ComboBox::from_id_salt("chart_row")
+                    .selected_text(name(id))
+                    .show_ui(ui, |ui| {
+                        for option in unfinished.iter() {
+                            if ui.selectable_label(*option == id, name(*option)).clicked() {
+                                *chart_row = Some(*option);
+                            }
+                        }
+                    });
+            });
+            let chart = cache.chart(course, id);
+            if chart.points.is_empty() {
+                ui.colored_label(ui.visuals().error_fg_color, "The course can't be evaluated yet");
+                return;
+            }
+            draw_grade_chart(ui, &chart.points, &course.scale, chart.projection, course.number_format);
+            if let Some((score, grade)) = chart.projection {
+                let fmt = course.number_format;
+                ui.label(format!("Projection at your current average of {}: {}", fmt.percent(score, 1), fmt.percent(grade, 2)));
+            }
+        });
+    }
+
+    fn render_breakdown(&mut self, ui: &mut egui::Ui) {
+        let course = &self.courses[self.active];
+        let cache = &mut self.analysis;
+        ui.collapsing("Grade breakdown", |ui| {
+            let parts = cache.breakdown(course);
+            if parts.is_empty() {
+                ui.label("No weighted sections");
+                return;
+            }
+            ui.label("Points earned, lost and still open per section. Click a section to select its row.");
+            if let Some(id) = draw_breakdown(ui, parts, self.selected_row, course.number_format) {
+                self.selected_row = if self.selected_row == Some(id) { None } else { Some(id) };
+                self.focus_selected = self.selected_row.is_some();
+            }
+            let total = |f: fn(&analysis::Contribution) -> f32| course.number_format.number(parts.iter().map(f).sum::<f32>(), 2);
+            ui.label(format!(
+                "Earned {}, lost {}, ungraded {}",
+                total(|p| p.earned),
+                total(|p| p.lost),
+                total(|p| p.ungraded)
+            ));
+        });
+    }
+
+    fn render_explanation(&mut self, ui: &mut egui::Ui) {
+        let course = &self.courses[self.active];
+        let cache = &mut self.analysis;
+        ui.collapsing("How was this calculated?", |ui| {
+            let steps = match cache.explanation(course) {
+                Ok(steps) => steps,
+                Err(e) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                    return;
+                }
+            };
+            for (i, step) in steps.iter().enumerate() {
+                egui::CollapsingHeader::new(&step.title)
+                    .id_salt(("trace_step", i))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        for line in step.lines.iter() {
+                            ui.label(line);
+                        }
+                    });
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Copy as text").clicked() {
+                    ui.ctx().copy_text(trace::to_text(course, steps));
+                }
+                let path = trace::path_for(self.file_path.as_ref());
+                if ui.button("Save as text").on_hover_text(path.display().to_string()).clicked() {
+                    self.final_message = match std::fs::write(&path, trace::to_text(course, steps)) {
+                        Ok(()) => format!("Saved the explanation to {}", path.display()),
+                        Err(e) => format!("Could not save {}: {}", path.display(), e),
+                    };
+                }
+            });
+        });
+    }
+
+    fn render_history(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        ui.collapsing("History", |ui| {
+            ui.label("A snapshot of your final grade is taken whenever the marks, rows or anything that changes the grade does. History is saved next to the course file.");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.checkpoint_label).hint_text("After the midterm").desired_width(160.0));
+                if ui.button("Checkpoint").clicked() {
+                    let label = match self.checkpoint_label.trim() {
+                        "" => "Checkpoint".to_string(),
+                        label => label.to_string(),
+                    };
+                    let snapshot = Snapshot::of(course, label, history::now());
+                    course.history.record(snapshot);
+                    self.checkpoint_label.clear();
+                }
+                if !course.history.snapshots.is_empty() && ui.button("Clear").clicked() {
+                    course.history.snapshots.clear();
+                }
+            });
+
+            let snapshots = &course.history.snapshots;
+            if snapshots.is_empty() {
+                return;
+            }
+            let fmt = course.number_format;
+            draw_timeline(ui, snapshots, fmt);
+
+            let describe = |i: usize| {
+                let s = &snapshots[i];
+                let grade = s.grade.map(|g| fmt.percent(g, 2)).unwrap_or_else(|| "no grade".to_string());
+                format!("{} {} ({})", history::format_timestamp(s.timestamp), s.label, grade)
+            };
+            let last = snapshots.len() - 1;
+            let (from, to) = &mut self.diff_pair;
+            *from = (*from).min(last);
+            *to = (*to).min(last);
+            ui.horizontal(|ui| {
+                ui.label("Compare");
+                for (salt, index) in [("diff_from", &mut *from), ("diff_to", &mut *to)] {
+                    egui::ComboBox::from_id_salt(salt).selected_text(describe(*index)).show_ui(ui, |ui| {
+                        for i in 0..=last {
+                            ui.selectable_value(index, i, describe(i));
+                        }
+                    });
+                }
+            });
+            let changes = history::diff(&snapshots[*from], &snapshots[*to]);
+            if changes.is_empty() {
+                ui.label("No rows changed");
+            }
+            for change in changes.iter() {
+                ui.label(match change {
+                    RowChange::Added { section, marks } => format!("+ {}: {}", section, marks),
+                    RowChange::Removed { section, marks } => format!("− {}: {}", section, marks),
+                    RowChange::Changed { section, before, after } => format!("~ {}: {} → {}", section, before, after),
+                });
+            }
+        });
+    }
+
+    fn render_grade_scale(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        let before = Grading::of(course);
+        ui.collapsing("Grade scale", |ui| {
+            let mut remove = None;
+            egui::Grid::new("grade_scale").show(ui, |ui| {
+                ui.strong("Letter");
+                ui.strong("Minimum %");
+                ui.strong("Grade points");
+                ui.end_row();
+                for (i, step) in course.scale.steps.iter_mut().enumerate() {
+                    ui.add(egui::TextEdit::singleline(&mut step.letter).desired_width(40.0));
+                    ui.add(egui::DragValue::new(&mut step.min).speed(0.5).range(0.0..=200.0));
+                    ui.add(egui::DragValue::new(&mut step.points).speed(0.1).range(0.0..=10.0));
+                    if ui.small_button("❌").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                course.scale.steps.remove(i);
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add letter").clicked() {
+                    course.scale.steps.push(GradeStep { letter: String::new(), min: 0.0, points: 0.0 });
+                }
+                if ui.button("Reset to default").clicked() {
+                    course.scale = GradeScale::default();
+                }
+            });
+        });
+        record_grading(course, &mut self.undo[self.active], before);
+    }
+
+    fn render_sidebar(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::left("courses")
+            .resizable(true)
+            .default_width(140.0)
+            .show(ctx, |ui| {
+                ui.heading("Courses");
+                let mut destination = None;
+                for (i, course) in self.courses.iter().enumerate() {
+                    let name = if course.name.trim().is_empty() { "Untitled course" } else { course.name.as_str() };
+                    if ui.selectable_label(self.view == View::Course && i == self.active, name).clicked() {
+                        destination = Some(Destination::Course(i));
+                    }
+                }
+                if ui.button("Add course").clicked() {
+                    self.courses.push(Course::new());
+                    self.undo.push(UndoStack::default());
+                    destination = Some(Destination::Course(self.courses.len() - 1));
+                }
+                if self.courses.len() > 1 && self.view == View::Course && ui.button("Remove course").clicked() {
+                    self.remove_course();
+                }
+
+                ui.separator();
+                if ui.selectable_label(self.view == View::Semester, "Semester summary").clicked() {
+                    destination = Some(Destination::Semester);
+                }
+                if ui.selectable_label(self.view == View::Transcript, "Transcript").clicked() {
+                    destination = Some(Destination::Transcript);
+                }
+                if let Some(destination) = destination {
+                    self.navigate(destination);
+                }
+            });
+    }
+
+    /// Shows `destination` in the central panel. Opening a course starts it without results, and an index
+    /// past the last course, as left by removing one, opens the last course instead.
+    fn navigate(&mut self, destination: Destination) {
+        match destination {
+            Destination::Course(i) => {
+                self.active = i.min(self.courses.len() - 1);
+                self.view = View::Course;
+                self.evaluation = None;
+                self.scenario_results.clear();
+                self.final_message.clear();
+            }
+            Destination::Semester => self.view = View::Semester,
+            Destination::Transcript => self.view = View::Transcript,
+        }
+    }
+
+    /// Removes the open course with its undo history and opens the one that took its place.
+    /// The last course is never removed.
+    fn remove_course(&mut self) {
+        if self.courses.len() < 2 {
+            return;
+        }
+        self.courses.remove(self.active);
+        self.undo.remove(self.active);
+        self.navigate(Destination::Course(self.active));
+    }
+
+    fn render_semester(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Semester summary");
+            let summary = semester::summarize(&self.courses);
+            let format = self.courses[self.active].number_format;
+            egui::Grid::new("semester_grid").striped(true).show(ui, |ui| {
+                for header in ["Course", "Credits", "Basis", "Grade", "Letter", "Points"] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for (course, fmt) in summary.courses.iter().zip(self.courses.iter().map(|c| c.number_format)) {
+                    ui.label(&course.name);
+                    ui.label(fmt.plain(course.credits));
+                    ui.label(basis_name(course.basis));
+                    ui.label(course.grade.map(|g| fmt.percent(g, 2)).unwrap_or_else(|| "N/A".to_string()));
+                    ui.label(course.letter.clone().unwrap_or_else(|| "-".to_string()));
+                    match course.points {
+                        Some(points) => ui.label(fmt.number(points, 2)),
+                        None => ui.weak("not in GPA"),
+                    };
+                    ui.end_row();
+                }
+            });
+            ui.add_space(8.0);
+            match summary.gpa {
+                Some(gpa) => ui.strong(format!("Term GPA: {} over {} credits", format.number(gpa, 2), format.plain(summary.gpa_credits))),
+                None => ui.strong("Term GPA: N/A"),
+            };
+            ui.label(format!("Credits earned: {}", format.plain(summary.earned_credits)));
+        });
+    }
+
+    fn render_transcript(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Transcript");
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut self.transcript_path);
+                if ui.button("Export").clicked() {
+                    self.final_message = match self.transcript.save(self.transcript_path.as_ref()) {
+                        Ok(()) => format!("Exported to {}", self.transcript_path),
+                        Err(e) => format!("Could not export {}: {}", self.transcript_path, e),
+                    };
+                }
+                if ui.button("Import").clicked() {
+                    match Transcript::load(self.transcript_path.as_ref()) {
+                        Ok(transcript) => {
+                            self.transcript = transcript;
+                            self.final_message = format!("Imported {}", self.transcript_path);
+                        }
+                        Err(e) => self.final_message = format!("Could not import {}: {}", self.transcript_path, e),
+                    }
+                }
+            });
+            ui.label(&self.final_message);
+            ui.separator();
+
+            // GPA under every retake policy, so the effect of a retake is visible
+            let policies = [
+                (RetakePolicy::Replace, "Replace"),
+                (RetakePolicy::Average, "Average"),
+                (RetakePolicy::KeepAll, "Count every attempt"),
+            ];
+            let format = self.courses[self.active].number_format;
+            let gpa_text = |gpa: Option<f32>| gpa.map(|g| format.number(g, 2)).unwrap_or_else(|| "N/A".to_string());
+            egui::Grid::new("transcript_gpa").show(ui, |ui| {
+                ui.strong("Retake policy");
+                ui.strong("Cumulative GPA");
+                ui.strong("Major GPA");
+                ui.strong("Credits");
+                ui.end_row();
+                for (policy, name) in policies {
+                    ui.radio_value(&mut self.transcript.retake_policy, policy, name);
+                    ui.label(gpa_text(self.transcript.cumulative_gpa(policy)));
+                    ui.label(gpa_text(self.transcript.major_gpa(policy)));
+                    ui.label(format.plain(self.transcript.gpa_credits(policy)));
+                    ui.end_row();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("To reach a cumulative GPA of");
+                ui.add(egui::DragValue::new(&mut self.target_gpa).speed(0.01).range(0.0..=10.0));
+                ui.label("with");
+                ui.add(egui::DragValue::new(&mut self.next_credits).speed(0.5).range(0.0..=60.0));
+                ui.label("credits next term:");
+                match self.transcript.needed_term_gpa(self.target_gpa, self.next_credits) {
+                    Some(needed) if needed > self.transcript.max_points() => {
+                        ui.colored_label(ui.visuals().error_fg_color, format!("needs {}, out of reach", format.number(needed, 2)))
+                    }
+                    Some(needed) if needed <= 0.0 => ui.label("any grades will do"),
+                    Some(needed) => ui.strong(format!("term GPA of {}", format.number(needed, 2))),
+                    None => ui.label("N/A"),
+                };
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let mut remove_term = None;
+                for (t, term) in self.transcript.terms.iter_mut().enumerate() {
+                    egui::CollapsingHeader::new(format!("{} ({} courses)", term.name, term.courses.len()))
+                        .id_salt(("term", t))
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Term:");
+                                ui.text_edit_singleline(&mut term.name);
+                                if ui.small_button("Remove term").clicked() {
+                                    remove_term = Some(t);
+                                }
+                            });
+                            let mut remove = None;
+                            egui::Grid::new(("term_grid", t)).show(ui, |ui| {
+                                for header in ["Code", "Title", "Credits", "Letter", "Major", "Basis", ""] {
+                                    ui.strong(header);
+                                }
+                                ui.end_row();
+                                for (i, c) in term.courses.iter_mut().enumerate() {
+                                    ui.add(egui::TextEdit::singleline(&mut c.code).desired_width(70.0));
+                                    ui.add(egui::TextEdit::singleline(&mut c.title).desired_width(140.0));
+                                    ui.add(egui::DragValue::new(&mut c.credits).speed(0.5).range(0.0..=30.0));
+                                    ui.add(egui::TextEdit::singleline(&mut c.letter).desired_width(30.0));
+                                    ui.checkbox(&mut c.major, "");
+                                    basis_combo(ui, ("transcript_basis", t, i), &mut c.basis);
+                                    if ui.small_button("❌").clicked() {
+                                        remove = Some(i);
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                            if let Some(i) = remove {
+                                term.courses.remove(i);
+                            }
+                            if ui.button("Add course").clicked() {
+                                term.courses.push(TranscriptCourse {
+                                    code: String::new(),
+                                    title: String::new(),
+                                    credits: 3.0,
+                                    letter: String::new(),
+                                    major: false,
+                                    basis: GradingBasis::Graded,
+                                });
+                            }
+                        });
+                }
+                if let Some(t) = remove_term {
+                    self.transcript.terms.remove(t);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.term_name).on_hover_text("Name of the new term");
+                    if ui.button("Add empty term").clicked() {
+                        self.transcript.terms.push(Term { name: self.term_name.clone(), courses: Vec::new() });
+                    }
+                    if ui.button("Add this semester").on_hover_text("Adds every course in the sidebar with its current letter").clicked() {
+                        let missing = self.transcript.add_semester(self.term_name.clone(), &self.courses);
+                        self.final_message = missing.join("\n");
+                    }
+                });
+            });
+        });
+    }
+
+    fn render_schemes(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        let stack = &mut self.undo[self.active];
+        let before = Grading::of(course);
+        let mut remove = None;
+        let mut add = false;
+        ui.collapsing("Grading schemes", |ui| {
+            for (i, name) in course.schemes.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Scheme {}:", i + 1));
+                    ui.text_edit_singleline(name);
+                    if ui.small_button("❌").on_hover_text("Remove this weight column").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+
+            ui.horizontal(|ui| {
+                add = ui.button("Add scheme").clicked();
+                ui.label("Final grade uses:");
+                let selected = match course.scheme_choice {
+                    SchemeChoice::Designated(i) => course.schemes.get(i).cloned().unwrap_or_default(),
+                    SchemeChoice::Best => "Best scheme".to_string(),
+                };
+                egui::ComboBox::from_id_salt("scheme_choice")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut course.scheme_choice, SchemeChoice::Best, "Best scheme");
+                        for (i, name) in course.schemes.iter().enumerate() {
+                            ui.selectable_value(&mut course.scheme_choice, SchemeChoice::Designated(i), name);
+                        }
+                    });
+            });
+        });
+        record_grading(course, stack, before);
+
+        if remove.is_some() || add {
+            replace_course(course, stack, |after| match remove {
+                Some(i) => after.remove_scheme(i),
+                None => {
+                    let name = ((b'A' + after.schemes.len() as u8 % 26) as char).to_string();
+                    after.add_scheme(name);
+                }
+            });
+        }
+    }
+
+    fn render_curves(&mut self, ui: &mut egui::Ui) {
+        let course = &mut self.courses[self.active];
+        let before = Grading::of(course);
+        ui.collapsing("Curves", |ui| {
+            let sections: Vec<String> = course.rows.iter().map(|r| r.section.clone()).collect();
+            let mut remove = None;
+            for (i, c) in course.curves.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut c.enabled, "");
+                    egui::ComboBox::from_id_salt(("curve_target", i))
+                        .selected_text(c.target.to_string())
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut c.target, CurveTarget::Course, "Course grade");
+                            for name in sections.iter() {
+                                ui.selectable_value(&mut c.target, CurveTarget::Section(name.clone()), name);
+                            }
+                        });
+                    egui::ComboBox::from_id_salt(("curve_kind", i))
+                        .selected_text(match c.kind {
+                            CurveKind::AddPoints(_) => "Add points",
+                            CurveKind::LinearRescale { .. } => "Rescale top to 100",
+                            CurveKind::SquareRoot => "Square root",
+                            CurveKind::ShiftToMean { .. } => "Shift mean",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut c.kind, CurveKind::AddPoints(5.0), "Add points");
+                            ui.selectable_value(&mut c.kind, CurveKind::LinearRescale { top: 90.0 }, "Rescale top to 100");
+                            ui.selectable_value(&mut c.kind, CurveKind::SquareRoot, "Square root");
+                            ui.selectable_value(&mut c.kind, CurveKind::ShiftToMean { mean: 60.0, target: 70.0 }, "Shift mean");
+                        });
+                    match &mut c.kind {
+                        CurveKind::AddPoints(n) => {
+                            ui.add(egui::DragValue::new(n).speed(0.5).suffix(" pts"));
+                        }
+                        CurveKind::LinearRescale { top } => {
+                            ui.label("top score");
+                            ui.add(egui::DragValue::new(top).speed(0.5).range(1.0..=200.0));
+                        }
+                        CurveKind::SquareRoot => {}
+                        CurveKind::ShiftToMean { mean, target } => {
+                            ui.label("mean");
+                            ui.add(egui::DragValue::new(mean).speed(0.5));
+                            ui.label("to");
+                            ui.add(egui::DragValue::new(target).speed(0.5));
+                        }
+                    }
+                    if ui.small_button("❌").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                course.curves.remove(i);
+            }
+            if ui.button("Add curve").clicked() {
+                course.curves.push(Curve {
+                    target: CurveTarget::Course,
+                    kind: CurveKind::AddPoints(5.0),
+                    enabled: true,
+                });
+            }
+
+            if !course.curves.is_empty() && ui.button("Disable all curves").clicked() {
+                course.curves.iter_mut().for_each(|c| c.enabled = false);
+            }
+
+            // before/after view of every section
+            ui.horizontal(|ui| {
+                ui.label("Raw score needed for a curved");
+                ui.add(egui::DragValue::new(&mut self.curve_goal).speed(0.5).range(0.0..=100.0).suffix("%"));
+            });
+            let fmt = course.number_format;
+            egui::Grid::new("curve_preview").striped(true).show(ui, |ui| {
+                ui.strong("Section");
+                ui.strong("Before");
+                ui.strong("After");
+                ui.strong("Raw needed");
+                ui.end_row();
+                for row in course.rows.iter() {
+                    ui.label(&row.section);
+                    if let Some(grade) = row.f_section_grade {
+                        let before = grade * 100.0;
+                        ui.label(fmt.percent(before, 2));
+                        ui.label(fmt.percent(curve::curve_section(&course.curves, &row.section, before), 2));
+                    } else {
+                        ui.label("-");
+                        ui.label("-");
+                    }
+                    ui.label(fmt.percent(curve::uncurve_section(&course.curves, &row.section, self.curve_goal), 2));
+                    ui.end_row();
+                }
+            });
+        });
+        record_grading(course, &mut self.undo[self.active], before);
     }
 
     fn render_message(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::bottom("message_panel")
             .min_height(80.0)
             .show(ctx, |ui| {
+                let fmt = self.courses[self.active].number_format;
                 ui.horizontal(|ui| {
                     ui.label("Final Grade:");
-                    if let Some(grade) = self._final_grade {
-                        ui.label(format!("{:.2}", grade));
+                    if let Some(eval) = &self.evaluation {
+                        ui.label(fmt.number(eval.grade, 2));
+                        if let Some(letter) = &eval.letter {
+                            ui.strong(letter);
+                        }
                     } else {
                         ui.label("N/A");
                     }
                 });
+                ui.label(&self.final_message);
+                if let Some((low, high)) = self.evaluation.as_ref().and_then(|e| e.range) {
+                    let scale = &self.courses[self.active].scale;
+                    let letters = match (scale.letter(low), scale.letter(high)) {
+                        (Some(a), Some(b)) if a == b => format!(" ({})", a),
+                        (Some(a), Some(b)) => format!(" ({} to {})", a, b),
+                        _ => String::new(),
+                    };
+                    ui.label(format!("Range: {}–{}{}", fmt.number(low, 2), fmt.percent(high, 2), letters));
+                }
+                if let Some(eval) = &self.evaluation {
+                    if eval.schemes.len() > 1 {
+                        ui.horizontal_wrapped(|ui| {
+                            for (i, scheme) in eval.schemes.iter().enumerate() {
+                                let text = match &scheme.grade {
+                                    Ok(grade) => format!("{}: {}", scheme.name, fmt.percent(*grade, 2)),
+                                    Err(e) => format!("{}: {}", scheme.name, e),
+                                };
+                                if i == eval.scheme {
+                                    ui.strong(format!("{} (used)", text));
+                                } else {
+                                    ui.label(text);
+                                }
+                            }
+                        });
+                    }
+                    if eval.outcomes.iter().any(|o| o.fired) {
+                        ui.label(format!("Weighted average before rules: {}", fmt.percent(eval.weighted, 2)));
+                    }
+                    for effect in eval.curves.iter() {
+                        ui.label(format!("Curved {}: {} → {}", effect.target, fmt.percent(effect.before, 2), fmt.percent(effect.after, 2)));
+                    }
+                    for cap in eval.caps.iter() {
+                        ui.label(format!("Capped {}: {} → {}", cap.target, fmt.percent(cap.before, 2), fmt.percent(cap.after, 2)));
+                    }
+                    for warning in eval.warnings.iter() {
+                        ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", warning));
+                    }
+                    for outcome in eval.outcomes.iter() {
+                        let text = format!("{}: {}", outcome.rule, outcome.detail);
+                        if outcome.fired {
+                            ui.label(format!("✔ {} ({} → {})", text, fmt.percent(outcome.before, 2), fmt.percent(outcome.after, 2)));
+                        } else {
+                            ui.weak(text);
+                        }
+                    }
+                }
+                if !self.scenario_results.is_empty() {
+                    egui::Grid::new("scenario_comparison").striped(true).show(ui, |ui| {
+                        ui.strong("Scenario");
+                        ui.strong("Final grade");
+                        ui.strong("Letter");
+                        ui.end_row();
+                        ui.label("Actual");
+                        match &self.evaluation {
+                            Some(eval) => {
+                                ui.label(fmt.percent(eval.grade, 2));
+                                ui.label(eval.letter.clone().unwrap_or_default());
+                            }
+                            None => {
+                                ui.label("N/A");
+                                ui.label("");
+                            }
+                        }
+                        ui.end_row();
+                        for (name, result) in self.scenario_results.iter() {
+                            ui.label(name);
+                            match result {
+                                Ok(e) => {
+                                    ui.label(fmt.percent(e.grade, 2));
+                                    ui.label(e.letter.clone().unwrap_or_default());
+                                }
+                                Err(e) => {
+                                    ui.colored_label(ui.visuals().error_fg_color, e);
+                                    ui.label("");
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                }
             });
     }
 
@@ -134,53 +1554,91 @@ impl MyApp {
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        
+        self.handle_shortcuts(ctx);
+        self.render_shortcuts(ctx);
         self.render_header(ctx);
-        self.render_grid(ctx);
-        self.render_message(ctx);
-        
+        self.render_sidebar(ctx);
+        match self.view {
+            View::Semester => return self.render_semester(ctx),
+            View::Transcript => return self.render_transcript(ctx),
+            View::Course => {}
+        }
+
+        // side and bottom panels go before the central grid so it fills what is left
         egui::TopBottomPanel::bottom("add_row_panel")
             .min_height(40.0)
             .show(ctx, |ui| {
                 // force left alignment
                 ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
                     if ui.button("Add row").clicked() {
-                        self.rows.push(RowFields::new());
+                        let course = &mut self.courses[self.active];
+                        let index = course.rows.len();
+                        self.undo[self.active].execute(Command::InsertRow { index, row: RowFields::new() }, course);
                     }
                     if ui.button("Calculate").clicked() {
-                        let mut status = true;
-                        let mut grades = Vec::new();
-                        let mut weights = Vec::new();
-                        // TODO enforce matching indices
-                        for row in self.rows.iter() {
-                            if let Some(grade) = row.f_section_grade{
-                                if let Ok(weight) = row.weight.parse::<f32>() {
-                                    grades.push(grade);
-                                    weights.push(weight);
-                                }
-                                else {
-                                    // Change this whole thing to a function, return the message (either success or error)
-                                    // and set the message to be displayed
-                                    status = false;
-                                }
-                            }
-                        }
-                        if status {
-                            self._final_grade = Calculator::weighted_sum(&grades, &weights);
-                            self._final_message = if let Some(grade) = self._final_grade {
-                                format!("Final grade is {:.2}%", grade)
-                            } else {
-                                String::from("Error calculating final grade")
-                            };
-                        }
-                        else {
-                            self._final_message = String::from("Each grade must have a corresponding weight");
-                        }
-                        
+                        self.calculate();
                     }
                 });
             });
+        self.render_message(ctx);
+        self.render_grid(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(courses: &[&str]) -> MyApp {
+        let mut app = MyApp::new();
+        app.courses = courses
+            .iter()
+            .map(|name| Course { name: name.to_string(), ..Course::new() })
+            .collect();
+        app.undo = courses.iter().map(|_| UndoStack::default()).collect();
+        app
+    }
+
+    #[test]
+    fn test_navigate_between_views() {
+        let mut app = app(&["Math", "Physics"]);
+        app.final_message = "Final grade is 90.00%".to_string();
+
+        app.navigate(Destination::Semester);
+        assert_eq!((&app.view, app.active), (&View::Semester, 0));
+        // Other views leave the open course and its results alone
+        assert_eq!(app.final_message, "Final grade is 90.00%");
+        app.navigate(Destination::Transcript);
+        assert_eq!(app.view, View::Transcript);
 
-        
+        app.navigate(Destination::Course(1));
+        assert_eq!((&app.view, app.active), (&View::Course, 1));
+        assert!(app.final_message.is_empty());
+        app.navigate(Destination::Semester);
+        app.navigate(Destination::Course(0));
+        assert_eq!((&app.view, app.active), (&View::Course, 0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_navigate_to_removed_course() {
+        let mut app = app(&["Math", "Physics", "Chemistry"]);
+        app.navigate(Destination::Course(2));
+        app.remove_course();
+        assert_eq!(app.courses.len(), 2);
+        assert_eq!(app.undo.len(), 2);
+        assert_eq!((&app.view, app.active), (&View::Course, 1));
+        assert_eq!(app.courses[app.active].name, "Physics");
+
+        // A destination kept from before the removal opens the last course
+        app.navigate(Destination::Semester);
+        app.navigate(Destination::Course(2));
+        assert_eq!((&app.view, app.active), (&View::Course, 1));
+
+        app.navigate(Destination::Course(0));
+        app.remove_course();
+        assert_eq!((app.active, app.courses[0].name.as_str()), (0, "Physics"));
+        // The last course stays
+        app.remove_course();
+        assert_eq!(app.courses.len(), 1);
+    }
+}
@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calculator::{Lexer, Token};
+
+/// Character between the whole and the fractional part of a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DecimalSeparator {
+    /// `87.5`, with `,` between function arguments
+    #[default]
+    Point,
+    /// `87,5`, with `;` between function arguments
+    Comma,
+}
+
+/// How numbers are written in marks and shown as grades.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct NumberFormat {
+    pub decimal: DecimalSeparator,
+}
+
+impl NumberFormat {
+    pub fn decimal_char(self) -> char {
+        match self.decimal {
+            DecimalSeparator::Point => '.',
+            DecimalSeparator::Comma => ',',
+        }
+    }
+
+    /// Separates function arguments. With a decimal comma it is `;`, so `clamp(87,5; 0; 100)` reads one way only.
+    pub fn argument_separator(self) -> char {
+        match self.decimal {
+            DecimalSeparator::Point => ',',
+            DecimalSeparator::Comma => ';',
+        }
+    }
+
+    /// Parses a number literal: digits with `_` between them, one decimal separator and an optional exponent.
+    pub fn parse(self, text: &str) -> Option<f32> {
+        let allowed = |c: char| c.is_ascii_digit() || matches!(c, '_' | 'e' | 'E' | '+' | '-') || c == self.decimal_char();
+        if !text.chars().all(allowed) {
+            return None;
+        }
+        let bytes = text.as_bytes();
+        for (i, b) in bytes.iter().enumerate() {
+            let digit_at = |j: Option<usize>| j.and_then(|j| bytes.get(j)).is_some_and(u8::is_ascii_digit);
+            if *b == b'_' && !(digit_at(i.checked_sub(1)) && digit_at(Some(i + 1))) {
+                return None;
+            }
+        }
+        let value: f32 = if self.decimal == DecimalSeparator::Point && !text.contains('_') {
+            text.parse().ok()?
+        } else {
+            // Rewritten with a decimal point and without `_` on the stack, as every allowed character is ASCII.
+            // Nobody types a number longer than the buffer into a marks field.
+            let mut buffer = [0u8; 64];
+            let mut len = 0;
+            for b in bytes.iter().filter(|b| **b != b'_') {
+                *buffer.get_mut(len)? = if *b as char == self.decimal_char() { b'.' } else { *b };
+                len += 1;
+            }
+            std::str::from_utf8(&buffer[..len]).ok()?.parse().ok()?
+        };
+        // Too large for an f32, such as `1e39`, rather than infinity
+        value.is_finite().then_some(value)
+    }
+
+    /// Writes `value` with as many decimals as it needs, e.g. a cutoff of `87.5` or `50` as typed.
+    pub fn plain(self, value: f32) -> String {
+        let text = value.to_string();
+        match self.decimal {
+            DecimalSeparator::Point => text,
+            DecimalSeparator::Comma => text.replace('.', ","),
+        }
+    }
+
+    pub fn number(self, value: f32, decimals: usize) -> String {
+        let text = format!("{:.*}", decimals, value);
+        match self.decimal {
+            DecimalSeparator::Point => text,
+            DecimalSeparator::Comma => text.replace('.', ","),
+        }
+    }
+
+    pub fn percent(self, value: f32, decimals: usize) -> String {
+        format!("{}%", self.number(value, decimals))
+    }
+
+    /// Rewrites the numbers and argument separators of a marks expression written in `self` into `to`.
+    /// Text that doesn't tokenize is left alone.
+    pub fn convert(self, marks: &str, to: NumberFormat) -> String {
+        let Ok(tokens) = Lexer::tokenize_in(marks, self) else {
+            return marks.to_string();
+        };
+        let mut text = marks.to_string();
+        for (token, span) in tokens.iter().rev() {
+            let (from, into) = match token {
+                Token::Number(_) => (self.decimal_char(), to.decimal_char()),
+                Token::Operator(',') => (self.argument_separator(), to.argument_separator()),
+                _ => continue,
+            };
+            let replaced = text[span.clone()].replace(from, &into.to_string());
+            text.replace_range(span.clone(), &replaced);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMA: NumberFormat = NumberFormat { decimal: DecimalSeparator::Comma };
+
+    #[test]
+    fn test_parse_literals() {
+        let point = NumberFormat::default();
+        assert_eq!(point.parse("1e2"), Some(100.0));
+        assert_eq!(point.parse("1.5E-3"), Some(0.0015));
+        assert_eq!(point.parse("1_000"), Some(1000.0));
+        assert_eq!(point.parse("87,5"), None);
+        assert_eq!(point.parse("1__0"), None);
+        assert_eq!(point.parse("_1"), None);
+        assert_eq!(point.parse("1_"), None);
+        assert_eq!(COMMA.parse("87,5"), Some(87.5));
+        assert_eq!(COMMA.parse("1_000,25"), Some(1000.25));
+        assert_eq!(COMMA.parse("87.5"), None);
+        assert_eq!(COMMA.parse(&"1".repeat(70)), None);
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        let point = NumberFormat::default();
+        assert_eq!(point.parse("3e38"), Some(3e38));
+        assert_eq!(point.parse("1e39"), None);
+        assert_eq!(point.parse(".97E202"), None);
+        assert_eq!(point.parse("5e910"), None);
+        assert_eq!(point.parse(&"1".repeat(70)), None);
+        assert_eq!(COMMA.parse("1_000e40"), None);
+        assert_eq!(point.parse("1e-50"), Some(0.0));
+    }
+
+    #[test]
+    fn test_display_and_convert() {
+        assert_eq!(COMMA.percent(87.456, 2), "87,46%");
+        assert_eq!(NumberFormat::default().number(87.456, 1), "87.5");
+        assert_eq!(COMMA.plain(87.5), "87,5");
+        assert_eq!(COMMA.plain(50.0), "50");
+        assert_eq!(
+            NumberFormat::default().convert("clamp(87.5, 0, 1e2) / [30..40.5]", COMMA),
+            "clamp(87,5; 0; 1e2) / [30..40,5]"
+        );
+        assert_eq!(COMMA.convert("round(87,5; 1)", NumberFormat::default()), "round(87.5, 1)");
+        assert_eq!(COMMA.convert("87.5", NumberFormat::default()), "87.5");
+    }
+}
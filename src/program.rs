@@ -0,0 +1,188 @@
+use crate::calculator::{self, Expr, Function};
+
+// One step of a compiled expression, working on a stack of values
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Number(f32),
+    /// Pushes the value in this slot
+    Load(usize),
+    Negate,
+    /// Replaces the two bounds of a range with their midpoint
+    Midpoint,
+    Binary(char),
+    Call { function: Function, argc: usize },
+}
+
+/// An expression flattened into stack operations, with one slot per unknown,
+/// for evaluating the same marks over many sets of values without walking the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    ops: Vec<Op>,
+    slots: Vec<String>,
+    // Most values on the stack at once
+    depth: usize,
+}
+
+impl Program {
+    /// Compiles `expr`, folding the parts without unknowns into numbers as it goes. Parts that
+    /// fail to evaluate are kept as they are, so running the program reports the same error.
+    pub fn compile(expr: &Expr) -> Result<Program, String> {
+        let mut program = Program { ops: Vec::new(), slots: expr.variables(), depth: 0 };
+        program.emit(expr, 0)?;
+        Ok(program)
+    }
+
+    /// Names of the unknowns, in the order their values are passed to `evaluate`.
+    pub fn slots(&self) -> &[String] {
+        &self.slots
+    }
+
+    #[cfg(test)]
+    pub fn evaluate(&self, values: &[f32]) -> Result<f32, String> {
+        self.evaluate_in(values, &mut Vec::with_capacity(self.depth))
+    }
+
+    /// Like `evaluate`, but reuses `stack` so a loop over many sets of values doesn't allocate.
+    pub fn evaluate_in(&self, values: &[f32], stack: &mut Vec<f32>) -> Result<f32, String> {
+        stack.clear();
+        for op in self.ops.iter() {
+            match op {
+                Op::Number(n) => stack.push(*n),
+                Op::Load(slot) => match values.get(*slot) {
+                    Some(value) => stack.push(*value),
+                    None => return Err(format!("Unknown value: {}", self.slots[*slot])),
+                },
+                Op::Negate => {
+                    let top = stack.last_mut().expect("operand on the stack");
+                    *top = -*top;
+                }
+                Op::Midpoint | Op::Binary(_) => {
+                    let right = stack.pop().expect("right operand on the stack");
+                    let left = stack.last_mut().expect("left operand on the stack");
+                    *left = match op {
+                        Op::Midpoint => (*left + right) / 2.0,
+                        // The operators that can't fail skip the error handling
+                        Op::Binary('+') => *left + right,
+                        Op::Binary('-') => *left - right,
+                        Op::Binary('*') => *left * right,
+                        Op::Binary(op) => calculator::binary(*op, *left, right)?,
+                        _ => unreachable!(),
+                    };
+                }
+                Op::Call { function, argc } => {
+                    let start = stack.len() - argc;
+                    let value = function.apply(&stack[start..])?;
+                    stack.truncate(start);
+                    stack.push(value);
+                }
+            }
+        }
+        Ok(stack.pop().expect("result on the stack"))
+    }
+
+    // Appends the operations for `expr`, which starts with `height` values already on the stack
+    fn emit(&mut self, expr: &Expr, height: usize) -> Result<(), String> {
+        match expr {
+            Expr::Number(n) => self.push(Op::Number(*n), height + 1),
+            Expr::Variable(name) => {
+                let slot = self.slots.iter().position(|s| s == name).expect("every unknown has a slot");
+                self.push(Op::Load(slot), height + 1)
+            }
+            Expr::Range { low, high } => {
+                self.emit(low, height)?;
+                self.emit(high, height + 1)?;
+                self.push(Op::Midpoint, height + 1)
+            }
+            Expr::UnaryOp { op: '+', operand } => self.emit(operand, height),
+            Expr::UnaryOp { op: '-', operand } => {
+                self.emit(operand, height)?;
+                self.push(Op::Negate, height + 1)
+            }
+            Expr::UnaryOp { op, .. } => Err(format!("Unknown unary operator: {}", op)),
+            Expr::BinaryOp { op, left, right } => {
+                self.emit(left, height)?;
+                self.emit(right, height + 1)?;
+                self.push(Op::Binary(*op), height + 1)
+            }
+            Expr::Call { name, args } => {
+                let function = Function::named(name).ok_or_else(|| format!("Unknown function: {}", name))?;
+                for (i, arg) in args.iter().enumerate() {
+                    self.emit(arg, height + i)?;
+                }
+                self.push(Op::Call { function, argc: args.len() }, height + 1)
+            }
+            Expr::Invalid => Err("Invalid expression".to_string()),
+        }
+    }
+
+    // Appends `op`, after which `height` values are on the stack. When all of its operands are
+    // numbers, it is replaced with its result, so a folded part never has to be looked at again.
+    fn push(&mut self, op: Op, height: usize) -> Result<(), String> {
+        self.depth = self.depth.max(height);
+        let arity = match op {
+            Op::Number(_) | Op::Load(_) => 0,
+            Op::Negate => 1,
+            Op::Midpoint | Op::Binary(_) => 2,
+            Op::Call { argc, .. } => argc,
+        };
+        let start = self.ops.len() - arity;
+        if arity > 0 && self.ops[start..].iter().all(|op| matches!(op, Op::Number(_))) {
+            let part = Program { ops: [&self.ops[start..], std::slice::from_ref(&op)].concat(), slots: Vec::new(), depth: arity };
+            if let Ok(value) = part.evaluate_in(&[], &mut Vec::with_capacity(arity)) {
+                self.ops.truncate(start);
+                self.ops.push(Op::Number(value));
+                return Ok(());
+            }
+        }
+        self.ops.push(op);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::Calculator;
+
+    #[test]
+    fn test_matches_tree() {
+        let formulas = [
+            "(25 + 10 + x) / (50 + 20 + 80)",
+            "-x ^ 2 + [y..10] * 2",
+            "clamp(x / y, 0, 1) * 100 % 7",
+            "round(sqrt(x)) + floor(-y) - ceil(x / 3)",
+            "x / (y - 2)",
+            "sqrt(x - 50) + 1 / 0 * y",
+        ];
+        let values = [(0.0, 2.0), (45.5, 3.0), (81.0, -4.25), (100.0, 2.0)];
+        for formula in formulas {
+            let expr = Calculator::parse(formula).unwrap();
+            let program = Program::compile(&expr).unwrap();
+            let mut stack = Vec::new();
+            for (x, y) in values {
+                let lookup = |name: &str| Some(if name == "x" { x } else { y });
+                let bound: Vec<f32> = program.slots().iter().map(|name| lookup(name).unwrap()).collect();
+                let tree = expr.evaluate_with(&lookup);
+                assert_eq!(program.evaluate_in(&bound, &mut stack), tree, "{} with x = {}, y = {}", formula, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slots_and_folding() {
+        let program = Program::compile(&Calculator::parse("(25 + 10 + x) / (50 + 20 + 80) * y + x").unwrap()).unwrap();
+        assert_eq!(program.slots(), ["x", "y"]);
+        // Both constant sums are folded into a single number
+        assert_eq!(program.ops.iter().filter(|op| matches!(op, Op::Number(_))).count(), 2);
+        assert_eq!(program.evaluate(&[45.0]).unwrap_err(), "Unknown value: y");
+        assert_eq!(program.evaluate(&[45.0, 1.5]), Ok(80.0 / 150.0 * 1.5 + 45.0));
+        assert!(Program::compile(&Expr::Invalid).is_err());
+        let program = Program::compile(&Calculator::parse("sqrt(-(2 * [1..3]) + 8) * x").unwrap()).unwrap();
+        assert_eq!(program.ops, [Op::Number(2.0), Op::Load(0), Op::Binary('*')]);
+
+        // Calls are resolved to the function when compiling
+        let program = Program::compile(&Calculator::parse("round(x) + clamp(x, 0, 1)").unwrap()).unwrap();
+        assert!(program.ops.contains(&Op::Call { function: Function::Round, argc: 1 }));
+        assert!(program.ops.contains(&Op::Call { function: Function::Clamp, argc: 3 }));
+    }
+}
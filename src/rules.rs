@@ -0,0 +1,394 @@
+use std::fmt;
+
+use crate::course::{SectionGrade, weighted_grade};
+use crate::number_format::NumberFormat;
+
+#[derive(Debug, PartialEq)]
+pub enum RuleError {
+    UnknownRule(usize, String),
+    Expected(usize, &'static str),
+    InvalidNumber(usize, String),
+    UnterminatedQuote(usize),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::UnknownRule(line, s) => write!(f, "Line {}: unknown rule '{}'", line, s),
+            RuleError::Expected(line, s) => write!(f, "Line {}: expected {}", line, s),
+            RuleError::InvalidNumber(line, s) => write!(f, "Line {}: invalid number '{}'", line, s),
+            RuleError::UnterminatedQuote(line) => write!(f, "Line {}: missing closing quote", line),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// A grading rule applied on top of the weighted average.
+///
+/// Percentages (`minimum`, `fail_grade`, `threshold`) are on the same 0-100 scale
+/// as the final grade, while section grades stay fractions as returned by the parser.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// `require Final >= 50 else 49`
+    Require { section: String, minimum: f32, fail_grade: f32 },
+    /// `replace Midterm with Final if higher`
+    Replace { target: String, source: String },
+    /// `cap Midterm, Final below 50`
+    CapAtAverage { sections: Vec<String>, threshold: f32 },
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Require { section, minimum, fail_grade } => {
+                write!(f, "require {} >= {} else {}", quote(section), minimum, fail_grade)
+            }
+            Rule::Replace { target, source } => {
+                write!(f, "replace {} with {} if higher", quote(target), quote(source))
+            }
+            Rule::CapAtAverage { sections, threshold } => {
+                let names: Vec<String> = sections.iter().map(|s| quote(s)).collect();
+                write!(f, "cap {} below {}", names.join(", "), threshold)
+            }
+        }
+    }
+}
+
+fn quote(name: &str) -> String {
+    if name.contains(char::is_whitespace) || name.contains([',', '#']) {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// What happened when a rule was evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleOutcome {
+    pub rule: Rule,
+    pub fired: bool,
+    pub before: f32,
+    pub after: f32,
+    pub detail: String,
+}
+
+/// Parses one rule per line. Blank lines and `#` comments are ignored, except for a `#` in a quoted name.
+pub fn parse_rules(src: &str) -> Result<Vec<Rule>, RuleError> {
+    let mut rules = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words = split_words(line, line_no)?;
+        rules.push(parse_rule(&words, line_no)?);
+    }
+    Ok(rules)
+}
+
+// The line up to the first `#` outside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Splits a line into words, keeping "quoted names" together and commas as their own word
+fn split_words(line: &str, line_no: usize) -> Result<Vec<String>, RuleError> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err(RuleError::UnterminatedQuote(line_no)),
+                    }
+                }
+                words.push(quoted.trim().to_string());
+            }
+            ',' => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                words.push(",".to_string());
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+fn parse_rule(words: &[String], line_no: usize) -> Result<Rule, RuleError> {
+    let mut words = words.iter().map(String::as_str).peekable();
+    let keyword = words.next().unwrap_or("");
+
+    let expect = |word: Option<&str>, expected: &'static str| match word {
+        Some(w) if w.eq_ignore_ascii_case(expected) => Ok(()),
+        _ => Err(RuleError::Expected(line_no, expected)),
+    };
+    let name = |word: Option<&str>| match word {
+        Some(w) if w != "," => Ok(w.to_string()),
+        _ => Err(RuleError::Expected(line_no, "a section name")),
+    };
+    let number = |word: Option<&str>| match word {
+        Some(w) => w
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .map_err(|_| RuleError::InvalidNumber(line_no, w.to_string())),
+        None => Err(RuleError::Expected(line_no, "a number")),
+    };
+
+    let rule = match keyword.to_ascii_lowercase().as_str() {
+        "require" => {
+            let section = name(words.next())?;
+            expect(words.next(), ">=")?;
+            let minimum = number(words.next())?;
+            expect(words.next(), "else")?;
+            let fail_grade = number(words.next())?;
+            Rule::Require { section, minimum, fail_grade }
+        }
+        "replace" => {
+            let target = name(words.next())?;
+            expect(words.next(), "with")?;
+            let source = name(words.next())?;
+            expect(words.next(), "if")?;
+            expect(words.next(), "higher")?;
+            Rule::Replace { target, source }
+        }
+        "cap" => {
+            let mut sections = vec![name(words.next())?];
+            while words.next_if_eq(&",").is_some() {
+                sections.push(name(words.next())?);
+            }
+            expect(words.next(), "below")?;
+            let threshold = number(words.next())?;
+            Rule::CapAtAverage { sections, threshold }
+        }
+        _ => return Err(RuleError::UnknownRule(line_no, keyword.to_string())),
+    };
+
+    match words.next() {
+        None => Ok(rule),
+        Some(_) => Err(RuleError::Expected(line_no, "end of line")),
+    }
+}
+
+fn find<'a>(sections: &'a [SectionGrade], name: &str) -> Option<&'a SectionGrade> {
+    sections.iter().find(|s| s.name.trim().eq_ignore_ascii_case(name))
+}
+
+/// Applies `rules` to a weighted average that was computed from `sections`.
+///
+/// Section-level rules (`replace`) run first and recompute the weighted average,
+/// then grade-level rules (`require`, `cap`) run in the order they were written.
+/// `sections` is updated in place so callers can show the adjusted section grades,
+/// and the details show grades the way `format` writes them.
+pub fn apply_rules(rules: &[Rule], sections: &mut [SectionGrade], weighted: f32, format: NumberFormat) -> (f32, Vec<RuleOutcome>) {
+    let mut grade = weighted;
+    let mut outcomes = Vec::new();
+
+    for rule in rules.iter().filter(|r| matches!(r, Rule::Replace { .. })) {
+        let Rule::Replace { target, source } = rule else { continue };
+        let before = grade;
+        let outcome = |fired: bool, after: f32, detail: String| RuleOutcome {
+            rule: rule.clone(),
+            fired,
+            before,
+            after,
+            detail,
+        };
+
+        let source_grade = find(sections, source).and_then(|s| s.grade);
+        let target_idx = sections.iter().position(|s| s.name.trim().eq_ignore_ascii_case(target));
+        match (target_idx, source_grade) {
+            (None, _) => outcomes.push(outcome(false, grade, format!("no section named {}", target))),
+            (_, None) => outcomes.push(outcome(false, grade, format!("{} has no grade yet", source))),
+            (Some(i), Some(new)) => match sections[i].grade {
+                Some(old) if new > old => {
+                    sections[i].grade = Some(new);
+                    grade = weighted_grade(sections).unwrap_or(grade);
+                    outcomes.push(outcome(
+                        true,
+                        grade,
+                        format!("{} {} replaced by {} {}", target, format.percent(old * 100.0, 2), source, format.percent(new * 100.0, 2)),
+                    ));
+                }
+                Some(_) => outcomes.push(outcome(false, grade, format!("{} is not higher than {}", source, target))),
+                None => outcomes.push(outcome(false, grade, format!("{} has no grade yet", target))),
+            },
+        }
+    }
+
+    for rule in rules {
+        let before = grade;
+        let (fired, detail) = match rule {
+            Rule::Replace { .. } => continue,
+            Rule::Require { section, minimum, fail_grade } => {
+                match find(sections, section).and_then(|s| s.grade) {
+                    None => (false, format!("{} has no grade yet", section)),
+                    Some(g) if g * 100.0 < *minimum && grade > *fail_grade => {
+                        grade = *fail_grade;
+                        (true, format!("{} is {}, below the required {}%", section, format.percent(g * 100.0, 2), format.plain(*minimum)))
+                    }
+                    Some(g) => (false, format!("{} is {}", section, format.percent(g * 100.0, 2))),
+                }
+            }
+            Rule::CapAtAverage { sections: names, threshold } => {
+                let listed: Vec<&SectionGrade> = names
+                    .iter()
+                    .filter_map(|n| find(sections, n))
+                    .filter(|s| s.grade.is_some())
+                    .collect();
+                match average(&listed) {
+                    None => (false, format!("{} have no grades yet", names.join(", "))),
+                    Some(avg) if avg < *threshold && grade > avg => {
+                        grade = avg;
+                        (true, format!("average of {} is {}, below {}%", names.join(", "), format.percent(avg, 2), format.plain(*threshold)))
+                    }
+                    Some(avg) => (false, format!("average of {} is {}", names.join(", "), format.percent(avg, 2))),
+                }
+            }
+        };
+        outcomes.push(RuleOutcome { rule: rule.clone(), fired, before, after: grade, detail });
+    }
+
+    (grade, outcomes)
+}
+
+// Weight-weighted average of the given sections as a percentage,
+// falling back to a plain mean when none of them carry weight
+fn average(sections: &[&SectionGrade]) -> Option<f32> {
+    if sections.is_empty() {
+        return None;
+    }
+    let total_weight: f32 = sections.iter().map(|s| s.weight).sum();
+    let avg = if total_weight > 0.0 {
+        sections.iter().map(|s| s.grade.unwrap_or(0.0) * s.weight).sum::<f32>() / total_weight
+    } else {
+        sections.iter().map(|s| s.grade.unwrap_or(0.0)).sum::<f32>() / sections.len() as f32
+    };
+    Some(avg * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::close;
+
+    fn sample() -> Vec<SectionGrade> {
+        vec![
+            SectionGrade::new("Assignments", Some(0.9), 30.0),
+            SectionGrade::new("Midterm", Some(0.4), 30.0),
+            SectionGrade::new("Final", Some(0.6), 40.0),
+        ]
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules(
+            "# exams\nrequire Final >= 50 else 49\nreplace Midterm with Final if higher\ncap \"Midterm\", Final below 50%\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                Rule::Require { section: "Final".to_string(), minimum: 50.0, fail_grade: 49.0 },
+                Rule::Replace { target: "Midterm".to_string(), source: "Final".to_string() },
+                Rule::CapAtAverage { sections: vec!["Midterm".to_string(), "Final".to_string()], threshold: 50.0 },
+            ]
+        );
+        assert_eq!(parse_rules(&rules[2].to_string()).unwrap()[0], rules[2]);
+    }
+
+    #[test]
+    fn test_hash_in_quoted_names() {
+        let error = parse_rules("require \"Lab #2\" >= 50 else 40 # labs\nreplace Lab#1 with \"Lab #2\" if higher").unwrap_err();
+        // Unquoted, the `#` still starts a comment
+        assert_eq!(error, RuleError::Expected(2, "with"));
+
+        let rules = parse_rules("require \"Lab #2\" >= 50 else 40 # labs\nreplace \"Lab#1\" with \"Lab #2\" if higher").unwrap();
+        assert_eq!(rules[0], Rule::Require { section: "Lab #2".to_string(), minimum: 50.0, fail_grade: 40.0 });
+        assert_eq!(rules[1], Rule::Replace { target: "Lab#1".to_string(), source: "Lab #2".to_string() });
+        assert_eq!(parse_rules(&rules[1].to_string()).unwrap()[0], rules[1]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_rules("drop Final"), Err(RuleError::UnknownRule(1, "drop".to_string())));
+        assert_eq!(parse_rules("\nrequire Final >= 50"), Err(RuleError::Expected(2, "else")));
+        assert_eq!(parse_rules("require Final >= x else 0"), Err(RuleError::InvalidNumber(1, "x".to_string())));
+        assert_eq!(parse_rules("cap \"Final below 50"), Err(RuleError::UnterminatedQuote(1)));
+        assert!(parse_rules("replace Midterm with Final if higher please").is_err());
+    }
+
+    #[test]
+    fn test_require() {
+        let rules = parse_rules("require final >= 70 else 49").unwrap();
+        let mut sections = sample();
+        let (grade, outcomes) = apply_rules(&rules, &mut sections, 63.0, NumberFormat::default());
+        assert_eq!(grade, 49.0);
+        assert!(outcomes[0].fired);
+
+        let rules = parse_rules("require Final >= 50 else 49").unwrap();
+        let (grade, outcomes) = apply_rules(&rules, &mut sections, 63.0, NumberFormat::default());
+        assert_eq!(grade, 63.0);
+        assert!(!outcomes[0].fired);
+        let comma = NumberFormat { decimal: crate::number_format::DecimalSeparator::Comma };
+        let (_, outcomes) = apply_rules(&rules, &mut sections, 63.0, comma);
+        assert_eq!(outcomes[0].detail, "Final is 60,00%");
+    }
+
+    #[test]
+    fn test_replace() {
+        let rules = parse_rules("replace Midterm with Final if higher").unwrap();
+        let mut sections = sample();
+        let weighted = weighted_grade(&sections).unwrap();
+        let (grade, outcomes) = apply_rules(&rules, &mut sections, weighted, NumberFormat::default());
+        assert_eq!(sections[1].grade, Some(0.6));
+        assert!(close(grade, 69.0));
+        assert!(outcomes[0].fired);
+    }
+
+    #[test]
+    fn test_cap_at_average() {
+        let rules = parse_rules("cap Midterm, Final below 55").unwrap();
+        let mut sections = sample();
+        let weighted = weighted_grade(&sections).unwrap();
+        let (grade, outcomes) = apply_rules(&rules, &mut sections, weighted, NumberFormat::default());
+        // (0.4 * 30 + 0.6 * 40) / 70
+        assert!(close(grade, 51.428_57));
+        assert!(outcomes[0].fired);
+    }
+
+    #[test]
+    fn test_ungraded_sections_do_not_fire() {
+        let rules = parse_rules("require Final >= 50 else 0\nreplace Midterm with Final if higher").unwrap();
+        let mut sections = sample();
+        sections[2].grade = None;
+        let (grade, outcomes) = apply_rules(&rules, &mut sections, 50.0, NumberFormat::default());
+        assert_eq!(grade, 50.0);
+        assert!(outcomes.iter().all(|o| !o.fired));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::course::{Course, GradingBasis};
+
+/// One course's line in the semester summary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CourseSummary {
+    pub name: String,
+    pub credits: f32,
+    pub basis: GradingBasis,
+    pub grade: Option<f32>,
+    pub letter: Option<String>,
+    pub points: Option<f32>,
+    pub counts_for_gpa: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemesterSummary {
+    pub courses: Vec<CourseSummary>,
+    pub gpa: Option<f32>,
+    pub gpa_credits: f32,
+    pub earned_credits: f32,
+}
+
+/// Credit-weighted average of grade points.
+pub fn gpa<I: IntoIterator<Item = (f32, f32)>>(points_and_credits: I) -> Option<f32> {
+    let (total, credits) = points_and_credits
+        .into_iter()
+        .fold((0.0, 0.0), |(total, credits), (p, c)| (total + p * c, credits + c));
+    if credits > 0.0 { Some(total / credits) } else { None }
+}
+
+/// Evaluates every course and computes the term GPA from the graded ones.
+///
+/// Pass/fail and audit courses are listed but never count towards the GPA,
+/// and neither do courses that cannot be evaluated yet.
+pub fn summarize(courses: &[Course]) -> SemesterSummary {
+    let mut summaries = Vec::new();
+    for course in courses.iter() {
+        let grade = course.evaluate().ok().map(|e| e.grade);
+        let (letter, points) = match (course.basis, grade) {
+            (GradingBasis::Audit, _) => (Some("AU".to_string()), None),
+            (_, None) => (None, None),
+            (GradingBasis::PassFail, Some(g)) => {
+                let letter = if course.scale.passes(g) { "P" } else { "F" };
+                (Some(letter.to_string()), None)
+            }
+            (GradingBasis::Graded, Some(g)) => match course.scale.step_for(g) {
+                Some(step) => (Some(step.letter.clone()), Some(step.points)),
+                None => (None, None),
+            },
+        };
+        summaries.push(CourseSummary {
+            name: course.name.clone(),
+            credits: course.credits,
+            basis: course.basis,
+            grade,
+            counts_for_gpa: points.is_some(),
+            letter,
+            points,
+        });
+    }
+
+    let gpa_credits = summaries
+        .iter()
+        .filter(|s| s.counts_for_gpa)
+        .map(|s| s.credits)
+        .sum();
+    let earned_credits = summaries
+        .iter()
+        .filter(|s| match s.basis {
+            GradingBasis::Graded => s.points.is_some_and(|p| p > 0.0),
+            GradingBasis::PassFail => s.letter.as_deref() == Some("P"),
+            GradingBasis::Audit => false,
+        })
+        .map(|s| s.credits)
+        .sum();
+    let gpa = gpa(summaries.iter().filter_map(|s| s.points.map(|p| (p, s.credits))));
+
+    SemesterSummary { courses: summaries, gpa, gpa_credits, earned_credits }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, close};
+
+    fn course(name: &str, marks: &str, credits: f32, basis: GradingBasis) -> Course {
+        let mut course = test_util::course(&[("Everything", marks, "100")], "");
+        course.name = name.to_string();
+        course.credits = credits;
+        course.basis = basis;
+        course
+    }
+
+    #[test]
+    fn test_gpa() {
+        assert_eq!(gpa([(4.0, 3.0), (3.0, 1.0)]), Some(3.75));
+        assert_eq!(gpa([]), None);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let courses = vec![
+            course("Calculus", "95/100", 4.0, GradingBasis::Graded),
+            course("History", "84/100", 3.0, GradingBasis::Graded),
+            course("Pottery", "40/100", 1.0, GradingBasis::PassFail),
+            course("Music", "100/100", 2.0, GradingBasis::Audit),
+            course("Physics", "", 3.0, GradingBasis::Graded),
+        ];
+        let summary = summarize(&courses);
+        // (4.0 * 4 + 3.0 * 3) / 7
+        assert!(close(summary.gpa, 25.0 / 7.0));
+        assert_eq!(summary.gpa_credits, 7.0);
+        assert_eq!(summary.earned_credits, 7.0);
+        assert_eq!(summary.courses[1].letter.as_deref(), Some("B"));
+        assert_eq!(summary.courses[2].letter.as_deref(), Some("F"));
+        assert_eq!(summary.courses[3].letter.as_deref(), Some("AU"));
+        assert!(!summary.courses[4].counts_for_gpa);
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+
+use crate::calculator::{Expr, Lexer, Token};
+use crate::number_format::NumberFormat;
+use crate::program::Program;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolveError {
+    /// The expression has no unknown, or more than one
+    Unknowns(usize),
+    /// The target can't be reached by any value in the range
+    NoSolution { low: f32, high: f32 },
+    Evaluation(String),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Unknowns(0) => write!(f, "There is no unknown to solve for"),
+            SolveError::Unknowns(n) => write!(f, "Can only solve for one unknown, found {}", n),
+            SolveError::NoSolution { low, high } => write!(f, "No solution within [{}, {}]", low, high),
+            SolveError::Evaluation(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The value of the expression's single unknown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub name: String,
+    pub value: f32,
+    /// Whether the answer came from the exact linear form rather than bisection
+    pub exact: bool,
+    /// Whether every value gives the target, as when the unknown is multiplied by 0. `value` is then 0
+    pub any: bool,
+}
+
+// `a * x + b` if the expression is linear in `x`
+fn linear(expr: &Expr) -> Option<(f32, f32)> {
+    match expr {
+        Expr::Number(n) => Some((0.0, *n)),
+        Expr::Variable(_) => Some((1.0, 0.0)),
+        Expr::UnaryOp { op: '-', operand } => linear(operand).map(|(a, b)| (-a, -b)),
+        Expr::UnaryOp { operand, .. } => linear(operand),
+        Expr::Call { .. } if expr.variables().is_empty() => expr.evaluate().ok().map(|v| (0.0, v)),
+        Expr::Range { .. } | Expr::Call { .. } | Expr::Invalid => None,
+        Expr::BinaryOp { op, left, right } => {
+            let (la, lb) = linear(left)?;
+            let (ra, rb) = linear(right)?;
+            match op {
+                '+' => Some((la + ra, lb + rb)),
+                '-' => Some((la - ra, lb - rb)),
+                '*' if la == 0.0 => Some((lb * ra, lb * rb)),
+                '*' if ra == 0.0 => Some((la * rb, lb * rb)),
+                '/' if ra == 0.0 && rb != 0.0 => Some((la / rb, lb / rb)),
+                '%' | '^' if la == 0.0 && ra == 0.0 => expr.evaluate().ok().map(|v| (0.0, v)),
+                _ => None,
+            }
+        }
+    }
+}
+
+// The terms of a chain of `+`, left to right
+fn terms(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp { op: '+', left, right } => {
+            let mut both = terms(left);
+            both.extend(terms(right));
+            both
+        }
+        _ => vec![expr],
+    }
+}
+
+/// Largest sensible value for the unknown. In `(25 + 10 + x) / (50 + 20 + 80)` it is the total
+/// written in the same place as `x`, here 80; otherwise the whole total of an `earned / total` expression, or 100.
+pub fn upper_bound(expr: &Expr) -> f32 {
+    match expr {
+        Expr::BinaryOp { op: '/', left, right } if right.variables().is_empty() => {
+            let (earned, totals) = (terms(left), terms(right));
+            let item_total = earned
+                .iter()
+                .position(|term| matches!(term, Expr::Variable(_)))
+                .filter(|_| earned.len() == totals.len() && totals.len() > 1)
+                .map(|i| totals[i]);
+            item_total
+                .unwrap_or(right)
+                .evaluate()
+                .ok()
+                .filter(|t| *t > 0.0)
+                .unwrap_or(100.0)
+        }
+        _ => 100.0,
+    }
+}
+
+/// Finds the value of the single unknown in `expr` that makes it equal `target`, within `[0, high]`.
+///
+/// Expressions linear in the unknown are solved exactly; anything else is bisected,
+/// assuming the expression only rises or only falls across the range.
+pub fn solve(expr: &Expr, target: f32, high: f32) -> Result<Solution, SolveError> {
+    let names = expr.variables();
+    if names.len() != 1 {
+        return Err(SolveError::Unknowns(names.len()));
+    }
+    let name = names[0].clone();
+    let no_solution = SolveError::NoSolution { low: 0.0, high };
+
+    if let Some((a, b)) = linear(expr) {
+        if a == 0.0 {
+            return match (b - target).abs() < 1e-6 {
+                true => Ok(Solution { name, value: 0.0, exact: true, any: true }),
+                false => Err(no_solution),
+            };
+        }
+        let value = (target - b) / a;
+        return if (0.0..=high).contains(&value) {
+            Ok(Solution { name, value, exact: true, any: false })
+        } else {
+            Err(no_solution)
+        };
+    }
+
+    // Bisection evaluates the expression dozens of times, so it runs compiled
+    let program = Program::compile(expr).map_err(SolveError::Evaluation)?;
+    let mut stack = Vec::new();
+    let mut f = |x: f32| {
+        program.evaluate_in(&[x], &mut stack)
+            .map(|v| v - target)
+            .map_err(SolveError::Evaluation)
+    };
+    let (mut lo, mut hi) = (0.0_f32, high);
+    let (f_lo, f_hi) = (f(lo)?, f(hi)?);
+    // An exact zero has a sign like any other number, so the ends are checked before comparing signs
+    if f_lo == 0.0 {
+        return Ok(Solution { name, value: lo, exact: false, any: false });
+    }
+    if f_hi == 0.0 {
+        return Ok(Solution { name, value: hi, exact: false, any: false });
+    }
+    if f_lo.signum() == f_hi.signum() {
+        return Err(no_solution);
+    }
+    for _ in 0..60 {
+        let mid = (lo + hi) / 2.0;
+        if f(mid)?.signum() == f_lo.signum() {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(Solution { name, value: (lo + hi) / 2.0, exact: false, any: false })
+}
+
+/// Writes `value`, rounded to two decimals, in place of every `name` in the marks text.
+/// A `*` goes in front where the unknown was multiplied implicitly, so `2x` becomes `2*50` rather than `250`,
+/// and a negative value is put in parentheses, so `2 - x` becomes `2 - (-3)` and `x ^ 2` stays a square.
+pub fn substitute(marks: &str, name: &str, value: f32, format: NumberFormat) -> String {
+    let Ok(tokens) = Lexer::tokenize_in(marks, format) else {
+        return marks.to_string();
+    };
+    let rounded = (value * 100.0).round() / 100.0;
+    let number = match rounded < 0.0 {
+        true => format!("({})", format.plain(rounded)),
+        false => format.plain(rounded.abs()),
+    };
+    let mut text = marks.to_string();
+    // Back to front so earlier spans stay valid
+    for (i, (token, span)) in tokens.iter().enumerate().rev() {
+        let Token::Ident(ident) = token else { continue };
+        if *ident != name {
+            continue;
+        }
+        let operand_before = i
+            .checked_sub(1)
+            .map(|j| &tokens[j])
+            .filter(|(before, _)| matches!(before, Token::Number(_) | Token::Ident(_) | Token::Operator(')' | ']')));
+        let replacement = match operand_before {
+            Some((_, before)) if before.end == span.start => format!("*{}", number),
+            Some(_) => format!("* {}", number),
+            None => number.clone(),
+        };
+        text.replace_range(span.clone(), &replacement);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::Calculator;
+
+    fn solve_str(expr: &str, target: f32) -> Result<Solution, SolveError> {
+        let expr = Calculator::parse(expr).unwrap();
+        solve(&expr, target, upper_bound(&expr))
+    }
+
+    #[test]
+    fn test_linear() {
+        let solution = solve_str("(25 + 10 + x) / (50 + 20 + 80)", 0.75).unwrap();
+        assert_eq!(solution.name, "x");
+        assert!(solution.exact);
+        assert!((solution.value - 77.5).abs() < 1e-3);
+        // The unknown can't score more than its own total, written in the same place below
+        assert_eq!(
+            solve_str("(25 + 10 + x) / (50 + 20 + 80)", 0.95),
+            Err(SolveError::NoSolution { low: 0.0, high: 80.0 })
+        );
+        // Without a total per item the range is the section total
+        assert!((solve_str("(25 + 10 + x) / 150", 0.95).unwrap().value - 107.5).abs() < 1e-3);
+        assert_eq!(
+            solve_str("(25 + 10 + x) / 150", 1.5),
+            Err(SolveError::NoSolution { low: 0.0, high: 150.0 })
+        );
+        assert!((solve_str("-(x - 100) / 100", 0.25).unwrap().value - 75.0).abs() < 1e-3);
+        assert!(!solution.any);
+        // An unknown multiplied by 0 reaches the target with any value, or never
+        let solution = solve_str("(45 + 0 * x) / 50", 0.9).unwrap();
+        assert!(solution.any);
+        assert_eq!(solution.value, 0.0);
+        assert!(matches!(solve_str("(45 + 0 * x) / 50", 0.8), Err(SolveError::NoSolution { .. })));
+    }
+
+    #[test]
+    fn test_nonlinear() {
+        let solution = solve_str("x * x / 100", 0.81).unwrap();
+        assert!(!solution.exact);
+        assert!((solution.value - 9.0).abs() < 1e-3);
+        assert!((solve_str("40 / (40 + x)", 0.8).unwrap().value - 10.0).abs() < 1e-3);
+        assert!(matches!(solve_str("x * x / 100", 200.0), Err(SolveError::NoSolution { .. })));
+        // Falling to exactly the target at the top of the range
+        assert_eq!(solve_str("(100 - x) * (100 - x) / 100", 0.0).unwrap().value, 100.0);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let point = NumberFormat::default();
+        assert_eq!(substitute("(25 + 10 + x) / (50 + 20 + 80)", "x", 92.5, point), "(25 + 10 + 92.5) / (50 + 20 + 80)");
+        assert_eq!(substitute("x + x2 + x", "x", 1.0 / 3.0, point), "0.33 + x2 + 0.33");
+        let comma = NumberFormat { decimal: crate::number_format::DecimalSeparator::Comma };
+        assert_eq!(substitute("(10,5 + x) / 50", "x", 92.5, comma), "(10,5 + 92,5) / 50");
+        let negative = substitute("2 - x ^ 2", "x", -3.0, point);
+        assert_eq!(negative, "2 - (-3) ^ 2");
+        assert_eq!(Calculator::str_2_f(&negative).unwrap(), -7.0);
+        assert_eq!(substitute("2 - x", "x", -0.001, point), "2 - 0");
+    }
+
+    #[test]
+    fn test_substitute_implicit_multiplication() {
+        let point = NumberFormat::default();
+        let solved = substitute("2x / 200", "x", 50.0, point);
+        assert_eq!(solved, "2*50 / 200");
+        assert_eq!(Calculator::str_2_f(&solved).unwrap(), 0.5);
+        assert_eq!(substitute("(10 + 2x)/100", "x", 20.0, point), "(10 + 2*20)/100");
+        assert_eq!(substitute("2 x + (1)x + y x", "x", 3.0, point), "2 * 3 + (1)*3 + y * 3");
+    }
+
+    #[test]
+    fn test_unknown_count() {
+        assert_eq!(solve_str("45 / 50", 0.9), Err(SolveError::Unknowns(0)));
+        assert_eq!(solve_str("(x + y) / 100", 0.9), Err(SolveError::Unknowns(2)));
+        assert_eq!(solve_str("x * 0 + 1", 0.5), Err(SolveError::NoSolution { low: 0.0, high: 100.0 }));
+    }
+}
@@ -0,0 +1,22 @@
+use crate::course::Course;
+use crate::ui_elements::RowFields;
+
+/// A course with one row per (section, marks, weight) and the given rules.
+pub fn course(rows: &[(&str, &str, &str)], rules: &str) -> Course {
+    let mut course = Course::new();
+    course.rows.clear();
+    for (name, marks, weight) in rows.iter() {
+        let mut row = RowFields::new_named(name.to_string());
+        row.marks = marks.to_string();
+        row.weights = vec![weight.to_string()];
+        row.update_grade();
+        course.insert_row(course.rows.len(), row);
+    }
+    course.rules = rules.to_string();
+    course
+}
+
+/// Whether `a` is there and equal to `b` up to float rounding.
+pub fn close(a: impl Into<Option<f32>>, b: f32) -> bool {
+    a.into().is_some_and(|a| (a - b).abs() < 1e-4)
+}
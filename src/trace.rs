@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use crate::calculator::{Calculator, Expr};
+use crate::course::{self, COURSE_GRADE, Course, GradeChange, SectionKind};
+use crate::number_format::NumberFormat;
+
+/// A titled group of lines in the explanation of a final grade.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+impl TraceStep {
+    fn new(title: &str) -> Self {
+        Self { title: title.to_string(), lines: Vec::new() }
+    }
+}
+
+/// Explains how the course's final grade comes out of its marks, one stage of the evaluation per step.
+pub fn explain(course: &Course) -> Result<Vec<TraceStep>, String> {
+    let eval = course.evaluate()?;
+    let fmt = course.number_format;
+    let percent = |value: f32| fmt.percent(value, 2);
+    let changes = |step: &mut TraceStep, verb: &str, list: &[GradeChange], course_level: bool| {
+        for change in list.iter().filter(|c| (c.target == COURSE_GRADE) == course_level) {
+            step.lines.push(format!("{} {}: {} → {}", verb, change.target, percent(change.before), percent(change.after)));
+        }
+    };
+    let mut steps = Vec::new();
+
+    let mut marks = TraceStep::new("Section grades");
+    for row in course.rows.iter() {
+        if row.marks.trim().is_empty() {
+            marks.lines.push(format!("{}: no marks yet", row.section));
+            continue;
+        }
+        let line = Calculator::parse_in(&row.marks, fmt, course.parse_options).and_then(|expr| expr.trace()).map(|trace| {
+            let text: Vec<String> = trace.iter().map(|e| localized(e, fmt)).collect();
+            let grade = trace.last().map_or(String::new(), |e| percent(e.evaluate().unwrap_or(0.0) * 100.0));
+            match row.f_section_range {
+                Some(range) => format!(
+                    "{} = {}, the middle of {} to {}",
+                    text.join(" → "),
+                    grade,
+                    percent(range.low * 100.0),
+                    percent(range.high * 100.0)
+                ),
+                None => format!("{} = {}", text.join(" → "), grade),
+            }
+        });
+        marks.lines.push(match line {
+            Ok(line) => format!("{}: {}", row.section, line),
+            Err(e) => format!("{}: {}", row.section, e),
+        });
+    }
+    steps.push(marks);
+
+    let mut adjusted = TraceStep::new("Section curves and caps");
+    changes(&mut adjusted, "Curved", &eval.curves, false);
+    changes(&mut adjusted, "Capped", &eval.caps, false);
+    if !adjusted.lines.is_empty() {
+        steps.push(adjusted);
+    }
+
+    let title = match eval.schemes.len() {
+        1 => "Weighted sum".to_string(),
+        _ => format!("Weighted sum under scheme {}", eval.schemes[eval.scheme].name),
+    };
+    let mut sum = TraceStep::new(&title);
+    let mut terms = Vec::new();
+    for section in eval.sections.iter() {
+        let Some(grade) = section.grade else {
+            sum.lines.push(format!("{}: not graded yet, left out", section.name));
+            continue;
+        };
+        let points = grade * section.weight;
+        let extra = if section.kind == SectionKind::ExtraCredit { " (extra credit)" } else { "" };
+        sum.lines.push(format!(
+            "{}: {} × {}% = {}{}",
+            section.name,
+            percent(grade * 100.0),
+            fmt.number(section.weight, 2),
+            fmt.number(points, 2),
+            extra
+        ));
+        terms.push(fmt.number(points, 2));
+    }
+    sum.lines.push(format!("Total: {} = {}", terms.join(" + "), percent(eval.weighted)));
+    steps.push(sum);
+
+    if !eval.outcomes.is_empty() {
+        let mut rules = TraceStep::new("Rules");
+        for outcome in eval.outcomes.iter() {
+            rules.lines.push(match outcome.fired {
+                true => format!("{}: {} ({} → {})", outcome.rule, outcome.detail, percent(outcome.before), percent(outcome.after)),
+                false => format!("{}: {}, no change", outcome.rule, outcome.detail),
+            });
+        }
+        steps.push(rules);
+    }
+
+    let mut result = TraceStep::new("Final grade");
+    changes(&mut result, "Curved", &eval.curves, true);
+    changes(&mut result, "Capped", &eval.caps, true);
+    result.lines.push(format!("Final grade: {}", percent(eval.grade)));
+    if let Some(step) = course.scale.step_for(eval.grade) {
+        result.lines.push(format!("{} is {}% and above", step.letter, fmt.number(step.min, 2)));
+    }
+    steps.push(result);
+    Ok(steps)
+}
+
+// The expression as the course writes numbers
+fn localized(expr: &Expr, fmt: NumberFormat) -> String {
+    NumberFormat::default().convert(&expr.to_string(), fmt)
+}
+
+/// The text file an explanation is saved to, e.g. `course.explanation.txt` for `course.json`.
+pub fn path_for(course_path: &Path) -> PathBuf {
+    course::sidecar_path(course_path, "explanation.txt")
+}
+
+/// The explanation as plain text, for copying or saving.
+pub fn to_text(course: &Course, steps: &[TraceStep]) -> String {
+    let mut text = match course.name.trim() {
+        "" => "How the final grade was calculated\n".to_string(),
+        name => format!("How the final grade of {} was calculated\n", name),
+    };
+    for step in steps.iter() {
+        text.push_str(&format!("\n{}\n", step.title));
+        for line in step.lines.iter() {
+            text.push_str(&format!("  {}\n", line));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::course;
+
+    #[test]
+    fn test_explain_pipeline() {
+        let c = course(
+            &[("Assignments", "(25+10)/(50+20)", "40"), ("Final", "45/50", "60"), ("Quiz", "", "0")],
+            "",
+        );
+        let steps = explain(&c).unwrap();
+        let titles: Vec<&str> = steps.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["Section grades", "Weighted sum", "Final grade"]);
+        assert_eq!(steps[0].lines[0], "Assignments: (25 + 10) / (50 + 20) → 35 / 70 → 0.5 = 50.00%");
+        assert_eq!(steps[0].lines[2], "Quiz: no marks yet");
+        assert_eq!(steps[1].lines[0], "Assignments: 50.00% × 40.00% = 20.00");
+        assert_eq!(steps[1].lines.last().unwrap(), "Total: 20.00 + 54.00 = 74.00%");
+        assert_eq!(steps[2].lines[0], "Final grade: 74.00%");
+
+        let text = to_text(&c, &steps);
+        assert!(text.starts_with("How the final grade was calculated\n\nSection grades\n  Assignments: "));
+        assert_eq!(path_for(Path::new("dir/course.json")), PathBuf::from("dir/course.explanation.txt"));
+    }
+
+    #[test]
+    fn test_explain_rules_and_caps() {
+        let mut c = course(&[("Midterm", "40/50", "40"), ("Final", "45/100", "60")], "require Final >= 50 else 49");
+        c.grade_cap = Some(45.0);
+        let steps = explain(&c).unwrap();
+        let rules = steps.iter().find(|s| s.title == "Rules").unwrap();
+        assert_eq!(rules.lines.len(), 1);
+        assert!(rules.lines[0].contains("59.00% → 49.00%"), "{}", rules.lines[0]);
+        let result = steps.last().unwrap();
+        assert_eq!(result.lines[0], "Capped Course grade: 49.00% → 45.00%");
+    }
+}
@@ -0,0 +1,269 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::course::{Course, GradingBasis};
+use crate::grade_scale::GradeScale;
+use crate::semester;
+
+/// A finished course as it appears on the transcript.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptCourse {
+    pub code: String,
+    pub title: String,
+    pub credits: f32,
+    pub letter: String,
+    /// Counts towards the major GPA
+    #[serde(default)]
+    pub major: bool,
+    #[serde(default)]
+    pub basis: GradingBasis,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Term {
+    pub name: String,
+    pub courses: Vec<TranscriptCourse>,
+}
+
+/// How repeated attempts at the same course code count towards the GPA.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum RetakePolicy {
+    /// Only the latest attempt counts
+    #[default]
+    Replace,
+    /// The attempts' grade points are averaged and the credits count once
+    Average,
+    /// Every attempt counts as a separate course
+    KeepAll,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub terms: Vec<Term>,
+    #[serde(default)]
+    pub retake_policy: RetakePolicy,
+    #[serde(default)]
+    pub scale: GradeScale,
+}
+
+// Grade points and credits of one course after applying the retake policy
+struct Counted {
+    points: f32,
+    credits: f32,
+    major: bool,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self {
+            terms: Vec::new(),
+            retake_policy: RetakePolicy::Replace,
+            scale: GradeScale::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)
+    }
+
+    /// Records the semester's courses as a new term with the letter each course's own grade scale gives.
+    /// Courses that can't be evaluated yet are left out. The GPA looks letters up on the transcript's
+    /// scale, so a graded course whose letter isn't on it is reported and counts for nothing.
+    pub fn add_semester(&mut self, name: String, courses: &[Course]) -> Vec<String> {
+        let summary = semester::summarize(courses);
+        let mut missing = Vec::new();
+        let courses = summary
+            .courses
+            .into_iter()
+            .filter_map(|c| {
+                let letter = c.letter?;
+                if c.basis == GradingBasis::Graded && self.scale.points_for_letter(&letter).is_none() {
+                    missing.push(format!("{}: {} is not on the transcript's grade scale, so it isn't in the GPA", c.name, letter));
+                }
+                Some(TranscriptCourse {
+                    code: c.name.clone(),
+                    title: c.name,
+                    credits: c.credits,
+                    letter,
+                    major: false,
+                    basis: c.basis,
+                })
+            })
+            .collect();
+        self.terms.push(Term { name, courses });
+        missing
+    }
+
+    /// Courses that count towards the GPA under `policy`, in term order.
+    fn counted(&self, policy: RetakePolicy) -> Vec<Counted> {
+        // (code, attempts) in order of first appearance
+        let mut groups: Vec<(String, Vec<&TranscriptCourse>)> = Vec::new();
+        for course in self.terms.iter().flat_map(|t| t.courses.iter()) {
+            if course.basis != GradingBasis::Graded || self.scale.points_for_letter(&course.letter).is_none() {
+                continue;
+            }
+            let code = course.code.trim().to_ascii_lowercase();
+            match groups.iter_mut().find(|(c, _)| !code.is_empty() && *c == code) {
+                Some((_, attempts)) => attempts.push(course),
+                None => groups.push((code, vec![course])),
+            }
+        }
+
+        let points = |c: &TranscriptCourse| self.scale.points_for_letter(&c.letter).unwrap_or(0.0);
+        let mut counted = Vec::new();
+        for (_, attempts) in groups.iter() {
+            let latest = attempts[attempts.len() - 1];
+            match policy {
+                RetakePolicy::Replace => counted.push(Counted {
+                    points: points(latest),
+                    credits: latest.credits,
+                    major: latest.major,
+                }),
+                RetakePolicy::Average => counted.push(Counted {
+                    points: attempts.iter().map(|c| points(c)).sum::<f32>() / attempts.len() as f32,
+                    credits: latest.credits,
+                    major: latest.major,
+                }),
+                RetakePolicy::KeepAll => counted.extend(attempts.iter().map(|c| Counted {
+                    points: points(c),
+                    credits: c.credits,
+                    major: c.major,
+                })),
+            }
+        }
+        counted
+    }
+
+    pub fn cumulative_gpa(&self, policy: RetakePolicy) -> Option<f32> {
+        semester::gpa(self.counted(policy).iter().map(|c| (c.points, c.credits)))
+    }
+
+    /// GPA over the courses tagged as part of the major.
+    pub fn major_gpa(&self, policy: RetakePolicy) -> Option<f32> {
+        semester::gpa(
+            self.counted(policy)
+                .iter()
+                .filter(|c| c.major)
+                .map(|c| (c.points, c.credits)),
+        )
+    }
+
+    pub fn gpa_credits(&self, policy: RetakePolicy) -> f32 {
+        self.counted(policy).iter().map(|c| c.credits).sum()
+    }
+
+    /// Term GPA needed over `credits` new credits to bring the cumulative GPA to `target`.
+    pub fn needed_term_gpa(&self, target: f32, credits: f32) -> Option<f32> {
+        if credits <= 0.0 {
+            return None;
+        }
+        let counted = self.counted(self.retake_policy);
+        let total_points: f32 = counted.iter().map(|c| c.points * c.credits).sum();
+        let total_credits: f32 = counted.iter().map(|c| c.credits).sum();
+        Some((target * (total_credits + credits) - total_points) / credits)
+    }
+
+    /// Highest grade points any letter on the scale gives.
+    pub fn max_points(&self) -> f32 {
+        self.scale.steps.iter().map(|s| s.points).fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grade_scale::GradeStep;
+    use crate::test_util::{self, close};
+
+    fn course(code: &str, credits: f32, letter: &str, major: bool) -> TranscriptCourse {
+        TranscriptCourse {
+            code: code.to_string(),
+            title: code.to_string(),
+            credits,
+            letter: letter.to_string(),
+            major,
+            basis: GradingBasis::Graded,
+        }
+    }
+
+    fn sample() -> Transcript {
+        let mut transcript = Transcript::new();
+        transcript.terms.push(Term {
+            name: "Fall".to_string(),
+            courses: vec![
+                course("MATH 101", 4.0, "D", true),
+                course("HIST 110", 3.0, "A", false),
+                course("ART 100", 1.0, "P", false),
+            ],
+        });
+        transcript.terms.push(Term {
+            name: "Winter".to_string(),
+            courses: vec![course("math 101", 4.0, "B", true), course("CS 120", 3.0, "B+", true)],
+        });
+        transcript.terms[0].courses[2].basis = GradingBasis::PassFail;
+        transcript
+    }
+
+    #[test]
+    fn test_retake_policies() {
+        let t = sample();
+        // MATH 101 counts once as a B: (3.0 * 4 + 4.0 * 3 + 3.3 * 3) / 10
+        assert!(close(t.cumulative_gpa(RetakePolicy::Replace), 3.39));
+        // MATH 101 averages D and B to 2.0
+        assert!(close(t.cumulative_gpa(RetakePolicy::Average), 2.99));
+        // Both attempts count: (1.0 * 4 + 4.0 * 3 + 3.0 * 4 + 3.3 * 3) / 14
+        assert!(close(t.cumulative_gpa(RetakePolicy::KeepAll), 37.9 / 14.0));
+        assert_eq!(t.gpa_credits(RetakePolicy::Replace), 10.0);
+    }
+
+    #[test]
+    fn test_major_gpa() {
+        let t = sample();
+        // (3.0 * 4 + 3.3 * 3) / 7
+        assert!(close(t.major_gpa(RetakePolicy::Replace), 21.9 / 7.0));
+        assert_eq!(Transcript::new().major_gpa(RetakePolicy::Replace), None);
+    }
+
+    #[test]
+    fn test_needed_term_gpa() {
+        let t = sample();
+        // 33.9 points over 10 credits, aiming for 3.5 over 25 credits
+        assert!(close(t.needed_term_gpa(3.5, 15.0), (3.5 * 25.0 - 33.9) / 15.0));
+        assert_eq!(t.needed_term_gpa(3.5, 0.0), None);
+        assert_eq!(t.max_points(), 4.0);
+    }
+
+    #[test]
+    fn test_add_semester() {
+        let mut course = test_util::course(&[("Everything", "88/100", "100")], "");
+        course.name = "PHYS 101".to_string();
+
+        let mut t = Transcript::new();
+        assert!(t.add_semester("Summer".to_string(), &[course.clone(), Course::new()]).is_empty());
+        assert_eq!(t.terms[0].courses.len(), 1);
+        assert_eq!(t.terms[0].courses[0].letter, "B+");
+        assert!(close(t.cumulative_gpa(RetakePolicy::Replace), 3.3));
+
+        // A letter only the course's own scale has is kept, and reported as left out of the GPA
+        course.scale.steps.push(GradeStep { letter: "HD".to_string(), min: 88.0, points: 4.0 });
+        let missing = t.add_semester("Fall".to_string(), &[course]);
+        assert_eq!(missing, ["PHYS 101: HD is not on the transcript's grade scale, so it isn't in the GPA"]);
+        assert_eq!(t.terms[1].courses[0].letter, "HD");
+        assert!(close(t.cumulative_gpa(RetakePolicy::KeepAll), 3.3));
+    }
+}
@@ -1,40 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calculator::{Analysis, Calculator, Expr, Interval, ParseOptions, Span, TokenKind};
+use crate::course::SectionKind;
+use crate::number_format::NumberFormat;
+use crate::solver;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RowFields {
+    /// Stable identifier the course assigns, so other data can refer to the row
+    #[serde(default)]
+    pub id: u64,
     pub section: String,
     pub marks: String,
-    pub weight: String,
+    /// One weight per grading scheme of the course
+    pub weights: Vec<String>,
+    #[serde(default)]
+    pub kind: SectionKind,
+    /// Highest allowed section grade in percent
+    #[serde(default)]
+    pub cap: Option<f32>,
+    #[serde(skip)]
     pub section_grade: String,
+    #[serde(skip)]
     pub f_section_grade: Option<f32>,
+    /// Bounds of the section grade when the marks contain a range
+    #[serde(skip)]
+    pub f_section_range: Option<Interval>,
+    /// The course's number format, which the marks are written in
+    #[serde(skip)]
+    pub format: NumberFormat,
+    /// The course's parse options, which the marks are read with
+    #[serde(skip)]
+    pub parse_options: ParseOptions,
+    #[serde(skip)]
+    analysis: MarksAnalysis,
+}
+
+/// The `Analysis` of a row's marks with the text, format and options it was made from, so drawing,
+/// hovering and grading the row parse the marks once per change. Rows compare equal whatever it holds.
+#[derive(Debug, Clone, Default)]
+struct MarksAnalysis {
+    source: Option<(String, NumberFormat, ParseOptions)>,
+    analysis: Analysis,
+}
+
+impl PartialEq for MarksAnalysis {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl MarksAnalysis {
+    fn get(&mut self, text: &str, format: NumberFormat, options: ParseOptions) -> &Analysis {
+        let current = matches!(&self.source, Some((t, f, o)) if t == text && *f == format && *o == options);
+        if !current {
+            self.analysis = Analysis::new_in(text, format, options);
+            self.source = Some((text.to_string(), format, options));
+        }
+        &self.analysis
+    }
+}
+
+/// Id of a text cell in the grid: 0 is the section, 1 the marks and 2 onwards the weights.
+/// Cells lock the focus so Tab is left to the grid, which moves the focus itself.
+pub fn cell_id(row_id: u64, column: usize) -> egui::Id {
+    egui::Id::new(("grade_cell", row_id, column))
+}
+
+impl Default for RowFields {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RowFields {
     pub fn new() -> Self {
         Self {
+            id: 0,
             section: String::new(),
             marks: String::new(),
-            weight: String::new(),
+            weights: vec![String::new()],
+            kind: SectionKind::Regular,
+            cap: None,
             section_grade: ("%".to_string()),
-            f_section_grade: None
+            f_section_grade: None,
+            f_section_range: None,
+            format: NumberFormat::default(),
+            parse_options: ParseOptions::default(),
+            analysis: MarksAnalysis::default(),
         }
     }
 
     pub fn new_named(section_name: String) -> Self {
         Self {
+            id: 0,
             section: section_name,
             marks: String::new(),
-            weight: String::new(),
+            weights: vec![String::new()],
+            kind: SectionKind::Regular,
+            cap: None,
             section_grade: ("%".to_string()),
-            f_section_grade: None
+            f_section_grade: None,
+            f_section_range: None,
+            format: NumberFormat::default(),
+            parse_options: ParseOptions::default(),
+            analysis: MarksAnalysis::default(),
+        }
+    }
+
+    /// Draws the row. A selected row gets a highlighted section field, and `focus` moves the keyboard focus to it.
+    pub fn render(&mut self, ui: &mut egui::Ui, selected: bool, focus: bool) -> bool {
+        let mut section = egui::TextEdit::singleline(&mut self.section)
+            .id(cell_id(self.id, 0))
+            .lock_focus(true);
+        if selected {
+            section = section.background_color(ui.visuals().selection.bg_fill);
+        }
+        let section = ui.add(section);
+        if focus {
+            section.request_focus();
+            section.scroll_to_me(None);
+        }
+        let marks_id = cell_id(self.id, 1);
+        // Paren matching follows the text cursor of the last frame
+        let cursor = egui::TextEdit::load_state(ui.ctx(), marks_id)
+            .and_then(|state| state.cursor.char_range())
+            .filter(|_| ui.memory(|m| m.has_focus(marks_id)))
+            .map(|range| range.primary.index);
+        let (format, options) = (self.format, self.parse_options);
+        let analysis = &mut self.analysis;
+        let mut layouter = |ui: &egui::Ui, text: &str, _wrap_width: f32| {
+            let cursor = cursor.map(|c| byte_offset(text, c));
+            ui.fonts(|f| f.layout_job(marks_layout(ui, text, analysis.get(text, format, options), cursor)))
+        };
+        let output = egui::TextEdit::singleline(&mut self.marks)
+            .id(marks_id)
+            .lock_focus(true)
+            .layouter(&mut layouter)
+            .show(ui);
+        if let Some(pointer) = output.response.hover_pos() {
+            let index = output.galley.cursor_from_pos(pointer - output.galley_pos).ccursor.index;
+            let analysis = self.analysis.get(&self.marks, format, options);
+            if let Some(text) = marks_tooltip(&self.marks, analysis, byte_offset(&self.marks, index), format) {
+                output.response.clone().on_hover_text_at_pointer(text);
+            }
+        }
+        let resp = output.response;
+        for (i, weight) in self.weights.iter_mut().enumerate() {
+            ui.add(
+                egui::TextEdit::singleline(weight)
+                    .id(cell_id(self.id, 2 + i))
+                    .lock_focus(true),
+            );
+        }
+        ui.label(&self.section_grade);
+
+        let mut options_changed = false;
+        let unknowns = self.parsed().filter(|e| !e.variables().is_empty()).cloned();
+        let icon = match (&unknowns, self.kind) {
+            (Some(_), _) => "x?",
+            (None, SectionKind::ExtraCredit) => "★",
+            (None, SectionKind::Regular) => "⚙",
+        };
+        let mut new_marks = None;
+        ui.menu_button(icon, |ui| {
+            if let Some(expr) = &unknowns {
+                new_marks = self.render_solver(ui, expr);
+                ui.separator();
+            }
+            let canonical = Calculator::canonical_in(&self.marks, self.format, self.parse_options).ok().filter(|c| *c != self.marks);
+            if ui
+                .add_enabled(canonical.is_some(), egui::Button::new("Format marks"))
+                .on_hover_text("Rewrite the marks with standard spacing and only the parentheses they need")
+                .clicked()
+            {
+                new_marks = canonical;
+                ui.close_menu();
+            }
+            let mut extra = self.kind == SectionKind::ExtraCredit;
+            if ui.checkbox(&mut extra, "Extra credit").on_hover_text("Weight is added on top of the regular 100%").changed() {
+                self.kind = if extra { SectionKind::ExtraCredit } else { SectionKind::Regular };
+                options_changed = true;
+            }
+            let mut capped = self.cap.is_some();
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut capped, "Cap at").changed() {
+                    self.cap = if capped { Some(100.0) } else { None };
+                    options_changed = true;
+                }
+                if let Some(cap) = self.cap.as_mut() {
+                    options_changed |= ui.add(egui::DragValue::new(cap).speed(0.5).suffix("%")).changed();
+                }
+            });
+        });
+
+        if let Some(marks) = new_marks {
+            self.marks = marks;
+            options_changed = true;
+        }
+
+        resp.changed() || options_changed
+    }
+
+    // Solves the marks for their unknown and returns the filled-in marks if asked to
+    fn render_solver(&self, ui: &mut egui::Ui, expr: &Expr) -> Option<String> {
+        let target_id = egui::Id::new(("solve_target", self.id));
+        let mut target = ui.data_mut(|d| *d.get_temp_mut_or(target_id, 85.0_f32));
+        ui.horizontal(|ui| {
+            ui.label("Section grade of");
+            ui.add(egui::DragValue::new(&mut target).speed(0.5).suffix("%"));
+        });
+        ui.data_mut(|d| d.insert_temp(target_id, target));
+        match solver::solve(expr, target / 100.0, solver::upper_bound(expr)) {
+            Ok(solution) if solution.any => {
+                ui.label(format!("any value of {} gives this grade", solution.name));
+            }
+            Ok(solution) => {
+                ui.label(format!("needs {} = {}", solution.name, self.format.number(solution.value, 2)));
+                if ui.button("Fill in").clicked() {
+                    ui.close_menu();
+                    return Some(solver::substitute(&self.marks, &solution.name, solution.value, self.format));
+                }
+            }
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+            }
+        }
+        None
+    }
+
+    // Says when the marks go past the total and whether the row's cap will apply
+    fn describe_grade(&self, percent: f32) -> String {
+        match self.cap {
+            Some(cap) if percent > cap => format!("{} (capped at {}%)", self.format.percent(percent, 2), self.format.plain(cap)),
+            _ if percent > 100.0 && self.kind == SectionKind::Regular => {
+                format!("{} (above total)", self.format.percent(percent, 2))
+            }
+            _ => self.format.percent(percent, 2),
         }
     }
 
-    pub fn render(&mut self, ui: &mut egui::Ui) -> bool {
-        ui.add(egui::TextEdit::singleline(&mut self.section));
-        let resp = 
-            ui.add(egui::TextEdit::singleline(&mut self.marks));
-        ui.add(egui::TextEdit::singleline(&mut self.weight));
+    /// Number of text cells the row has in the grid.
+    pub fn cells(&self) -> usize {
+        2 + self.weights.len()
+    }
+
+    /// Weight under the given scheme, or an empty string if the row has none.
+    pub fn weight(&self, scheme: usize) -> &str {
+        self.weights.get(scheme).map(String::as_str).unwrap_or("")
+    }
+
+    // The marks as parsed, from the analysis kept for the current text
+    fn parsed(&mut self) -> Option<&Expr> {
+        self.analysis.get(&self.marks, self.format, self.parse_options).parsed()
+    }
 
-        resp.changed()
-    }   
+    /// Turns the parsed marks expression into the section grade.
+    pub fn update_grade(&mut self) {
+        self.f_section_range = None;
+        let parsed = self.parsed().cloned();
+        if self.marks.trim().is_empty() {
+            self.f_section_grade = None;
+            self.section_grade = "%".to_string();
+        } else if let Some(range) = parsed.as_ref().filter(|e| e.has_range()).and_then(|e| e.evaluate_interval().ok()) {
+            // The point grade is the midpoint, so charts and projections still have a value
+            self.f_section_grade = Some((range.low + range.high) / 2.0);
+            self.f_section_range = Some(range);
+            self.section_grade = format!("{}–{}", self.format.number(range.low * 100.0, 2), self.format.percent(range.high * 100.0, 2));
+        } else if let Some(Ok(grade)) = parsed.as_ref().map(Expr::evaluate) {
+            self.f_section_grade = Some(grade);
+            self.section_grade = self.describe_grade(grade * 100.0);
+        } else if let Some(expr) = parsed.filter(|e| !e.variables().is_empty()) {
+            // Counts as ungraded until the unknown is solved for
+            self.f_section_grade = None;
+            self.section_grade = format!("? (unknown {})", expr.variables().join(", "));
+        } else {
+            self.f_section_grade = None;
+            self.section_grade = "Error".to_string();
+        }
+    }
 }
 
 pub struct RowHeaders {
@@ -42,6 +286,30 @@ pub struct RowHeaders {
     pub section: String,
     pub marks: String,
     pub weight: String,
+    pub grade: String,
+}
+
+const MARKS_HELP: &str = "e.g. (25 + 10 + 76) / (50 + 20 + 80)
+Points earned / Total points possible
+
+Operators, tightest first:
+  ^          power, right to left: 2 ^ 3 ^ 2 = 2 ^ 9
+  - +        sign: -2 ^ 2 = -(2 ^ 2)
+             not after the same operator: 2 - (-3), not 2 - -3
+  * / %      multiply, divide, remainder
+             2(3), 2x and 2 3 multiply too, unless turned off
+  + -        add, subtract
+
+Numbers: 87.5, 1_000, 1e2 (87,5 with a decimal comma)
+Functions: round(x), floor(x), ceil(x), sqrt(x), clamp(x, low, high)
+  (with a decimal comma, arguments are split by ;)
+Ranges: [30..40] for a score you are unsure of
+Unknowns: a name such as x, to solve for";
+
+impl Default for RowHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RowHeaders {
@@ -51,13 +319,103 @@ impl RowHeaders {
             section: "Section".to_string(),
             marks: "Marks/Total".to_string(),
             weight: "Weight %".to_string(),
+            grade: "Grade".to_string(),
         }
     }
 
-    pub fn render(&self, ui: &mut egui::Ui) {
+    pub fn render(&self, ui: &mut egui::Ui, schemes: &[String]) {
         ui.label(&self.delete);
         ui.label(&self.section).on_hover_text_at_pointer("Section name");
-        ui.label(&self.marks).on_hover_text_at_pointer("e.g. (25 + 10 + 76) / (50 + 20 + 80)\nPoints earned / Total points possible");
-        ui.label(&self.weight).on_hover_text_at_pointer("Weight of this section in the overall grade\nEnter a number between 0 and 100");
+        ui.label(&self.marks).on_hover_text_at_pointer(MARKS_HELP);
+        for scheme in schemes.iter() {
+            let label = if schemes.len() > 1 {
+                format!("{} ({})", self.weight, scheme)
+            } else {
+                self.weight.clone()
+            };
+            ui.label(label).on_hover_text_at_pointer("Weight of this section in the overall grade\nEnter a number between 0 and 100");
+        }
+        ui.label(&self.grade);
+        ui.label("").on_hover_text_at_pointer("Extra credit and caps");
     }
-}
\ No newline at end of file
+}
+
+// Byte offset of the `chars`-th character
+fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices().nth(chars).map_or(text.len(), |(i, _)| i)
+}
+
+/// Colours numbers, operators and parentheses, highlights the parenthesis pair at the cursor
+/// and underlines the part of the expression an error points at.
+fn marks_layout(ui: &egui::Ui, text: &str, analysis: &Analysis, cursor: Option<usize>) -> egui::text::LayoutJob {
+    let visuals = ui.visuals();
+    let (number, paren) = if visuals.dark_mode {
+        (egui::Color32::from_rgb(181, 206, 168), egui::Color32::from_rgb(255, 215, 0))
+    } else {
+        (egui::Color32::from_rgb(9, 134, 88), egui::Color32::from_rgb(175, 0, 219))
+    };
+    let base = egui::TextFormat {
+        font_id: egui::TextStyle::Body.resolve(ui.style()),
+        color: visuals.text_color(),
+        ..Default::default()
+    };
+    let matched = cursor.and_then(|c| analysis.matching_paren(c));
+    let errors: Vec<Span> = analysis
+        .errors
+        .iter()
+        .map(|(_, span)| match span.len() {
+            // An error at the very end underlines the last character
+            0 => span.start.saturating_sub(1)..span.start.max(1),
+            _ => span.clone(),
+        })
+        .collect();
+
+    let format_at = |i: usize| {
+        let mut format = base.clone();
+        for (token, span) in analysis.tokens.iter() {
+            if span.contains(&i) {
+                format.color = match token {
+                    TokenKind::Number => number,
+                    TokenKind::Operator('(' | ')' | '[' | ']') => paren,
+                    TokenKind::Unknown => visuals.warn_fg_color,
+                    _ => visuals.strong_text_color(),
+                };
+            }
+        }
+        if let Some((a, b)) = &matched
+            && (a.contains(&i) || b.contains(&i))
+        {
+            format.background = visuals.selection.bg_fill.gamma_multiply(0.5);
+        }
+        if errors.iter().any(|span| span.contains(&i)) {
+            format.underline = egui::Stroke::new(1.5, visuals.error_fg_color);
+        }
+        format
+    };
+
+    let mut job = egui::text::LayoutJob::default();
+    let mut run_start = 0;
+    let mut run_format = format_at(0);
+    for (i, _) in text.char_indices().skip(1) {
+        let format = format_at(i);
+        if format != run_format {
+            job.append(&text[run_start..i], 0.0, std::mem::replace(&mut run_format, format));
+            run_start = i;
+        }
+    }
+    job.append(&text[run_start..], 0.0, run_format);
+    job
+}
+
+/// Value of the sub-expression at byte `pos`, or the error message if the marks don't evaluate.
+fn marks_tooltip(text: &str, analysis: &Analysis, pos: usize, format: NumberFormat) -> Option<String> {
+    if !analysis.errors.is_empty() {
+        let messages: Vec<&str> = analysis.errors.iter().map(|(message, _)| message.as_str()).collect();
+        return (!text.trim().is_empty()).then(|| messages.join("\n"));
+    }
+    let (span, expr) = analysis.node_at(pos)?;
+    let value = expr.evaluate().ok()?;
+    // Written out in full rather than rounded, then put into the course's number format
+    let value = format.plain(value);
+    Some(format!("{} = {}", text[span.clone()].trim(), value))
+}
@@ -0,0 +1,389 @@
+use crate::course::{Course, Scenario, SchemeChoice, SectionKind};
+use crate::curve::Curve;
+use crate::grade_scale::GradeScale;
+use crate::ui_elements::RowFields;
+
+/// A text field of a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Section,
+    Marks,
+    /// Weight under the scheme with this index
+    Weight(usize),
+}
+
+/// Whether a row is extra credit and where its grade is capped, set from the row menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowOptions {
+    pub kind: SectionKind,
+    pub cap: Option<f32>,
+}
+
+impl RowOptions {
+    pub fn of(row: &RowFields) -> Self {
+        Self { kind: row.kind, cap: row.cap }
+    }
+}
+
+/// What turns the section grades into the final grade: the panels below the grid.
+/// Adding or removing a scheme changes every row's weights, so that replaces the course instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grading {
+    pub rules: String,
+    pub grade_cap: Option<f32>,
+    pub curves: Vec<Curve>,
+    pub schemes: Vec<String>,
+    pub scheme_choice: SchemeChoice,
+    pub scale: GradeScale,
+}
+
+impl Grading {
+    pub fn of(course: &Course) -> Self {
+        Self {
+            rules: course.rules.clone(),
+            grade_cap: course.grade_cap,
+            curves: course.curves.clone(),
+            schemes: course.schemes.clone(),
+            scheme_choice: course.scheme_choice,
+            scale: course.scale.clone(),
+        }
+    }
+
+    fn set(&self, course: &mut Course) {
+        course.rules = self.rules.clone();
+        course.grade_cap = self.grade_cap;
+        course.curves = self.curves.clone();
+        if course.schemes.len() == self.schemes.len() {
+            course.schemes = self.schemes.clone();
+        }
+        course.scheme_choice = self.scheme_choice;
+        course.scale = self.scale.clone();
+    }
+}
+
+/// A reversible change to a course. Every edit made in the course view is one, except the course's name,
+/// credits and grading basis, which only matter to the semester and transcript views, and its history.
+#[derive(Debug, Clone)]
+pub enum Command {
+    InsertRow { index: usize, row: RowFields },
+    RemoveRow { index: usize, row: RowFields },
+    MoveRow { from: usize, to: usize },
+    EditField { row_id: u64, field: Field, before: String, after: String },
+    SetRowOptions { row_id: u64, before: RowOptions, after: RowOptions },
+    SetScenarios { before: Vec<Scenario>, after: Vec<Scenario> },
+    SetGrading { before: Box<Grading>, after: Box<Grading> },
+    /// Replaces the whole course, e.g. when opening a file. The history stays as it is
+    ReplaceCourse { before: Box<Course>, after: Box<Course> },
+}
+
+// What consecutive edits are merged by while the same field keeps the focus
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EditKey {
+    Field(u64, Field),
+    RowOptions(u64),
+    Scenarios,
+    Grading,
+}
+
+fn field_mut(row: &mut RowFields, field: Field) -> Option<&mut String> {
+    match field {
+        Field::Section => Some(&mut row.section),
+        Field::Marks => Some(&mut row.marks),
+        Field::Weight(i) => row.weights.get_mut(i),
+    }
+}
+
+impl Command {
+    fn apply(&mut self, course: &mut Course) {
+        match self {
+            Command::InsertRow { index, row } => {
+                // Keep the id the course assigns so a redo brings back the same row
+                row.id = course.insert_row(*index, row.clone());
+            }
+            Command::RemoveRow { index, .. } => {
+                course.rows.remove(*index);
+            }
+            Command::MoveRow { from, to } => {
+                let row = course.rows.remove(*from);
+                course.rows.insert(*to, row);
+            }
+            Command::EditField { row_id, field, after, .. } => set_field(course, *row_id, *field, after),
+            Command::SetRowOptions { row_id, after, .. } => set_options(course, *row_id, *after),
+            Command::SetScenarios { after, .. } => course.scenarios = after.clone(),
+            Command::SetGrading { after, .. } => after.set(course),
+            Command::ReplaceCourse { after, .. } => replace_course(course, after),
+        }
+    }
+
+    fn revert(&self, course: &mut Course) {
+        match self {
+            Command::InsertRow { index, .. } => {
+                course.rows.remove(*index);
+            }
+            Command::RemoveRow { index, row } => {
+                course.insert_row(*index, row.clone());
+            }
+            Command::MoveRow { from, to } => {
+                let row = course.rows.remove(*to);
+                course.rows.insert(*from, row);
+            }
+            Command::EditField { row_id, field, before, .. } => set_field(course, *row_id, *field, before),
+            Command::SetRowOptions { row_id, before, .. } => set_options(course, *row_id, *before),
+            Command::SetScenarios { before, .. } => course.scenarios = before.clone(),
+            Command::SetGrading { before, .. } => before.set(course),
+            Command::ReplaceCourse { before, .. } => replace_course(course, before),
+        }
+    }
+
+    fn edit_key(&self) -> Option<EditKey> {
+        match self {
+            Command::EditField { row_id, field, .. } => Some(EditKey::Field(*row_id, *field)),
+            Command::SetRowOptions { row_id, .. } => Some(EditKey::RowOptions(*row_id)),
+            Command::SetScenarios { .. } => Some(EditKey::Scenarios),
+            Command::SetGrading { .. } => Some(EditKey::Grading),
+            _ => None,
+        }
+    }
+}
+
+fn set_field(course: &mut Course, row_id: u64, field: Field, text: &str) {
+    if let Some(row) = course.rows.iter_mut().find(|r| r.id == row_id) {
+        if let Some(value) = field_mut(row, field) {
+            *value = text.to_string();
+        }
+        row.update_grade();
+    }
+}
+
+fn set_options(course: &mut Course, row_id: u64, options: RowOptions) {
+    if let Some(row) = course.rows.iter_mut().find(|r| r.id == row_id) {
+        row.kind = options.kind;
+        row.cap = options.cap;
+        row.update_grade();
+    }
+}
+
+// Snapshots taken since the command was recorded would be lost with the copy's history
+fn replace_course(course: &mut Course, with: &Course) {
+    let history = std::mem::take(&mut course.history);
+    *course = with.clone();
+    course.history = history;
+}
+
+/// Undo and redo history of one course.
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    done: Vec<Command>,
+    undone: Vec<Command>,
+    // Key of the edit still being typed, which later keystrokes merge into
+    open_edit: Option<EditKey>,
+}
+
+impl UndoStack {
+    /// Applies the command and records it.
+    pub fn execute(&mut self, mut command: Command, course: &mut Course) {
+        command.apply(course);
+        self.record(command);
+    }
+
+    /// Records a command whose change has already been made, such as text typed into a field.
+    /// Edits to the field that is still being typed into are merged into one step.
+    pub fn record(&mut self, command: Command) {
+        self.undone.clear();
+        let key = command.edit_key();
+        if key.is_some() && key == self.open_edit {
+            match (self.done.last_mut(), command) {
+                (Some(Command::EditField { after, .. }), Command::EditField { after: new, .. }) => *after = new,
+                (Some(Command::SetRowOptions { after, .. }), Command::SetRowOptions { after: new, .. }) => *after = new,
+                (Some(Command::SetScenarios { after, .. }), Command::SetScenarios { after: new, .. }) => *after = new,
+                (Some(Command::SetGrading { after, .. }), Command::SetGrading { after: new, .. }) => *after = new,
+                (_, command) => self.done.push(command),
+            }
+        } else {
+            self.done.push(command);
+        }
+        self.open_edit = key;
+    }
+
+    /// Stops merging edits, e.g. when the focus moves to another field.
+    pub fn end_edit(&mut self) {
+        self.open_edit = None;
+    }
+
+    pub fn undo(&mut self, course: &mut Course) -> bool {
+        self.open_edit = None;
+        match self.done.pop() {
+            Some(command) => {
+                command.revert(course);
+                self.undone.push(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, course: &mut Course) -> bool {
+        self.open_edit = None;
+        match self.undone.pop() {
+            Some(mut command) => {
+                command.apply(course);
+                self.done.push(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Snapshot;
+    use crate::number_format::{DecimalSeparator, NumberFormat};
+
+    fn sections(course: &Course) -> Vec<&str> {
+        course.rows.iter().map(|r| r.section.as_str()).collect()
+    }
+
+    #[test]
+    fn test_row_commands() {
+        let mut course = Course::new();
+        let mut stack = UndoStack::default();
+        let marks_row = course.rows[2].id;
+        course.rows[2].marks = "(25 + 10 + 76) / (50 + 20 + 80)".to_string();
+        course.rows[2].update_grade();
+
+        let row = course.rows[2].clone();
+        stack.execute(Command::RemoveRow { index: 2, row }, &mut course);
+        stack.execute(Command::InsertRow { index: 0, row: RowFields::new_named("Quiz".to_string()) }, &mut course);
+        stack.execute(Command::MoveRow { from: 1, to: 3 }, &mut course);
+        assert_eq!(sections(&course), ["Quiz", "Project", "Final", "Assignments"]);
+
+        assert!(stack.undo(&mut course));
+        assert!(stack.undo(&mut course));
+        assert!(stack.undo(&mut course));
+        assert_eq!(sections(&course), ["Assignments", "Project", "Midterm", "Final"]);
+        assert_eq!(course.rows[2].id, marks_row);
+        assert_eq!(course.rows[2].marks, "(25 + 10 + 76) / (50 + 20 + 80)");
+        assert!((course.rows[2].f_section_grade.unwrap() - 111.0 / 150.0).abs() < 1e-6);
+        assert!(!stack.undo(&mut course));
+
+        assert!(stack.redo(&mut course));
+        assert!(stack.redo(&mut course));
+        let quiz = course.rows[0].id;
+        assert!(stack.undo(&mut course));
+        assert!(stack.redo(&mut course));
+        assert_eq!(course.rows[0].id, quiz);
+        assert!(stack.can_redo());
+    }
+
+    #[test]
+    fn test_edits_merge_per_focus() {
+        let mut course = Course::new();
+        let mut stack = UndoStack::default();
+        let id = course.rows[0].id;
+        let mut typed = String::new();
+        for c in "40/50".chars() {
+            let before = typed.clone();
+            typed.push(c);
+            course.rows[0].marks = typed.clone();
+            stack.record(Command::EditField { row_id: id, field: Field::Marks, before, after: typed.clone() });
+        }
+        stack.end_edit();
+        course.rows[0].weights[0] = "30".to_string();
+        stack.record(Command::EditField {
+            row_id: id,
+            field: Field::Weight(0),
+            before: String::new(),
+            after: "30".to_string(),
+        });
+
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.rows[0].weights[0], "");
+        assert_eq!(course.rows[0].marks, "40/50");
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.rows[0].marks, "");
+        assert_eq!(course.rows[0].f_section_grade, None);
+        assert!(!stack.can_undo());
+
+        // A new edit drops whatever could have been redone
+        assert!(stack.redo(&mut course));
+        stack.record(Command::EditField { row_id: id, field: Field::Section, before: "Assignments".to_string(), after: "Labs".to_string() });
+        assert!(!stack.can_redo());
+    }
+
+    #[test]
+    fn test_scenarios_and_replace() {
+        let mut course = Course::new();
+        let mut stack = UndoStack::default();
+        let added = vec![Scenario { name: "Scenario 1".to_string(), ..Default::default() }];
+        course.scenarios = added.clone();
+        stack.record(Command::SetScenarios { before: Vec::new(), after: added });
+
+        let mut opened = Course::new();
+        opened.name = "Opened".to_string();
+        let before = Box::new(course.clone());
+        stack.execute(Command::ReplaceCourse { before, after: Box::new(opened) }, &mut course);
+        assert_eq!(course.name, "Opened");
+        assert!(course.scenarios.is_empty());
+
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.name, "");
+        assert_eq!(course.scenarios.len(), 1);
+        assert!(stack.undo(&mut course));
+        assert!(course.scenarios.is_empty());
+    }
+
+    #[test]
+    fn test_replace_keeps_history() {
+        let mut course = Course::new();
+        let mut stack = UndoStack::default();
+        let mut after = course.clone();
+        after.set_number_format(NumberFormat { decimal: DecimalSeparator::Comma });
+        stack.execute(Command::ReplaceCourse { before: Box::new(course.clone()), after: Box::new(after) }, &mut course);
+        course.history.record(Snapshot::of(&course, "After the switch".to_string(), 1000));
+
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.number_format, NumberFormat::default());
+        assert_eq!(course.history.snapshots.len(), 1);
+        assert!(stack.redo(&mut course));
+        assert_eq!(course.history.snapshots[0].label, "After the switch");
+    }
+
+    #[test]
+    fn test_grading_and_row_options() {
+        let mut course = Course::new();
+        let mut stack = UndoStack::default();
+        let before = Grading::of(&course);
+        course.rules = "require Final >= 50 else 49".to_string();
+        course.scheme_choice = SchemeChoice::Designated(0);
+        stack.record(Command::SetGrading { before: Box::new(before), after: Box::new(Grading::of(&course)) });
+        // Typing more of the rules is the same step
+        let before = Grading::of(&course);
+        course.rules.push_str("\ncap Midterm below 50");
+        stack.record(Command::SetGrading { before: Box::new(before), after: Box::new(Grading::of(&course)) });
+        stack.end_edit();
+
+        let id = course.rows[0].id;
+        let before = RowOptions::of(&course.rows[0]);
+        course.rows[0].cap = Some(90.0);
+        stack.record(Command::SetRowOptions { row_id: id, before, after: RowOptions::of(&course.rows[0]) });
+
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.rows[0].cap, None);
+        assert!(stack.undo(&mut course));
+        assert_eq!(course.rules, "");
+        assert_eq!(course.scheme_choice, SchemeChoice::Best);
+        assert!(!stack.can_undo());
+        assert!(stack.redo(&mut course));
+        assert_eq!(course.rules, "require Final >= 50 else 49\ncap Midterm below 50");
+    }
+}
@@ -0,0 +1,53 @@
+// Counts the allocations the marks hot path makes. Runs without the test harness, on the main
+// thread alone, so nothing else allocates while a count is taken.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use grade_calculator::calculator::{Calculator, Lexer};
+use grade_calculator::number_format::{DecimalSeparator, NumberFormat};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// Reads every token of `text`, which must lex without errors
+fn lex(text: &str, format: NumberFormat) -> usize {
+    let expected = Lexer::tokenize_in(text, format).expect(text).len() - 1;
+    allocations(|| assert_eq!(Lexer::new(text, format).count(), expected, "{}", text))
+}
+
+fn main() {
+    let comma = NumberFormat { decimal: DecimalSeparator::Comma };
+    let text = "(25 + 10 + x) / (50 + 20 + 80) * clamp(score_1, 0, 1e2) + [30..40.5] % 2 ^ 1_000.25";
+    assert_eq!(lex(text, NumberFormat::default()), 0);
+    assert_eq!(lex("clamp(87,5; 1_000,25; 1,5e-3) / [30..40,5]", comma), 0);
+    assert_eq!(allocations(|| assert_eq!(comma.parse("1_000,25"), Some(1000.25))), 0);
+
+    // Parsing only allocates the tree, about one box per operand
+    let parse = allocations(|| assert_eq!(Calculator::str_2_f("(25 + 10) / 50"), Ok(0.7)));
+    assert!(parse <= 4, "parsing (25 + 10) / 50 allocated {} times", parse);
+    let parse = allocations(|| assert!(Calculator::str_2_f("-87.5").is_ok()));
+    assert!(parse <= 1, "parsing -87.5 allocated {} times", parse);
+    println!("allocations: ok");
+}